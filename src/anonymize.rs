@@ -0,0 +1,217 @@
+use clap::Args;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex, RegexBuilder};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+/// Keys whose string value is a username.
+const USERNAME_KEYS: [&str; 4] = ["author", "editor", "uploader", "voter"];
+/// Keys whose object value describes a user (Crom's `createdBy`, `user`…).
+const USER_OBJECT_KEYS: [&str; 6] = ["createdBy", "user", "lastEditedBy", "author", "editor", "voter"];
+/// Keys holding the name inside a user object.
+const USER_NAME_KEYS: [&str; 4] = ["name", "displayName", "unixName", "username"];
+/// Keys holding user IDs or avatars inside a user object.
+const USER_ID_KEYS: [&str; 5] = ["wikidotId", "userId", "id", "avatar", "avatarUrl"];
+/// Keys whose text may mention users.
+const TEXT_KEYS: [&str; 6] = ["content", "quotes", "signature", "source", "title", "description"];
+
+lazy_static!(
+    static ref AN_REGEX_USER_MODULE: Regex = Regex::new(r"\[\[(\*?)user\s+([^\]]+?)\s*\]\]").unwrap();
+    static ref AN_REGEX_USER_INFO: Regex = Regex::new(r"user:info/([\w.\-]+)").unwrap();
+    static ref AN_REGEX_MENTION: Regex = Regex::new(r"(^|[^\w@/])@(\w[\w.\-]*\w|\w)").unwrap();
+    static ref AN_REGEX_CSS: Regex = Regex::new(r"(?is)\[\[module\s+css\b.*?\[\[/module\]\]|<style\b.*?</style>").unwrap();
+    static ref AN_REGEX_AVATAR: Regex = Regex::new(r#"<img[^>]*avatar\.php[^>]*>"#).unwrap();
+    static ref AN_REGEX_PRINTUSER: Regex = Regex::new(r#"(<span class="printuser[^"]*">(?:<a[^>]*><img[^>]*></a>)?<a[^>]*>)([^<]*)(</a>)"#).unwrap();
+    static ref AN_REGEX_AVATAR_ALT: Regex = Regex::new(r#"(<img[^>]*avatar\.php[^>]*alt=")([^"]*)(")"#).unwrap();
+    static ref AN_REGEX_USER_ID: Regex = Regex::new(r"(userid=|userInfo\()\d+").unwrap();
+);
+
+#[derive(Args, Debug)]
+pub struct AnonymizeParameters {
+    /// Replaces usernames (authors, page creators, mentions in contents…) by pseudonyms derived from the given salt.
+    /// Exports made with the same salt use the same pseudonyms.
+    #[arg(long, value_name = "SALT")]
+    pub anonymize: Option<String>,
+    /// With --anonymize, also removes user IDs and avatars.
+    #[arg(long, default_value = "false", requires = "anonymize")]
+    pub strip_user_ids: bool,
+    /// With --anonymize, also replaces the known usernames anywhere in texts, as whole words ignoring case.
+    /// Catches names written by hand, but also ordinary words that happen to be usernames.
+    #[arg(long, default_value = "false", requires = "anonymize")]
+    pub anonymize_free_text: bool,
+}
+
+impl AnonymizeParameters {
+    pub fn anonymizer(&self) -> Option<Anonymizer> {
+        self.anonymize.as_ref().map(|salt| Anonymizer {
+            salt: salt.clone(),
+            strip_user_ids: self.strip_user_ids,
+            free_text: self.anonymize_free_text,
+        })
+    }
+}
+
+/// Replaces usernames by salted stable pseudonyms in exported data.
+#[derive(Debug)]
+pub struct Anonymizer {
+    salt: String,
+    strip_user_ids: bool,
+    free_text: bool,
+}
+
+impl Anonymizer {
+    /// Computed from the user's Wikidot unix name ("Jane Doe" → "jane-doe"), so every spelling gets the same pseudonym.
+    pub fn pseudonym(&self, name: &str) -> String {
+        let unix_name = name.trim().to_lowercase().replace([' ', '_'], "-");
+        let digest = Sha256::new()
+            .chain_update(self.salt.as_bytes())
+            .chain_update([0])
+            .chain_update(unix_name.as_bytes())
+            .finalize();
+        format!("user-{}", digest.iter().take(5).map(|byte| format!("{byte:02x}")).join(""))
+    }
+
+    /// Anonymizes exported data: usernames first, then the texts mentioning any of them.
+    pub fn anonymize(&self, values: &mut [Value]) {
+        let names_regex = self._names_regex(values);
+        values.iter_mut().for_each(|value| self._anonymize_value(value, None, false, names_regex.as_ref()));
+    }
+
+    #[cfg(feature = "list-pages")]
+    /// Anonymizes downloaded webpages, with the usernames of the exported data they go with.
+    pub fn anonymize_htmls(&self, htmls: &[String], values: &[Value]) -> Box<[String]> {
+        let names_regex = self._names_regex(values);
+        htmls.iter().map(|html| self._anonymize_text(html, names_regex.as_ref())).collect()
+    }
+
+    /// Regex of the usernames found in the data, for --anonymize-free-text.
+    fn _names_regex(&self, values: &[Value]) -> Option<Regex> {
+        if !self.free_text {
+            return None;
+        }
+        let mut names = BTreeSet::new();
+        values.iter().for_each(|value| _collect_names(value, false, &mut names));
+        if names.is_empty() {
+            return None;
+        }
+        /* Longest names first, so "Jane Doe" is replaced before "Jane" */
+        let alternatives = names.iter()
+            .sorted_by_key(|name| std::cmp::Reverse(name.len()))
+            .map(|name| regex::escape(name))
+            .join("|");
+        RegexBuilder::new(format!(r"\b(?:{alternatives})\b").as_str())
+            .case_insensitive(true)
+            .size_limit(1 << 28)
+            .build()
+            .inspect_err(|e| eprintln!("[WARNING] Couldn't build the regex of usernames, names won't be replaced in texts: {e}"))
+            .ok()
+    }
+
+    /// `parent_is_user`: whether the object holding this value describes a user.
+    fn _anonymize_value(&self, value: &mut Value, key: Option<&str>, parent_is_user: bool, names: Option<&Regex>) {
+        match value {
+            Value::String(string) => {
+                if key.is_some_and(|key| _is_username_key(key, parent_is_user)) {
+                    if !_is_placeholder(string) {
+                        *string = self.pseudonym(string);
+                    }
+                } else if key.is_some_and(|key| TEXT_KEYS.contains(&key)) {
+                    *string = self._anonymize_text(string, names);
+                }
+            }
+            Value::Array(array) => array.iter_mut()
+                .for_each(|item| self._anonymize_value(item, key, parent_is_user, names)),
+            Value::Object(object) => {
+                let is_user = key.is_some_and(|key| _is_user_object_key(key, parent_is_user));
+                if is_user && self.strip_user_ids {
+                    USER_ID_KEYS.iter().for_each(|id_key| { object.remove(*id_key); });
+                }
+                object.iter_mut().for_each(|(child_key, child)| {
+                    self._anonymize_value(child, Some(child_key.as_str()), is_user, names)
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces the users mentioned in a text (HTML, Wikidot source or plain text).
+    fn _anonymize_text(&self, text: &str, names: Option<&Regex>) -> String {
+        let text = AN_REGEX_PRINTUSER.replace_all(text, |captures: &Captures| {
+            format!("{}{}{}", &captures[1], self._pseudonym_or_placeholder(&captures[2]), &captures[3])
+        });
+        let text = AN_REGEX_AVATAR_ALT.replace_all(&text, |captures: &Captures| {
+            format!("{}{}{}", &captures[1], self._pseudonym_or_placeholder(&captures[2]), &captures[3])
+        });
+        let text = AN_REGEX_USER_MODULE.replace_all(&text, |captures: &Captures| {
+            format!("[[{}user {}]]", &captures[1], self.pseudonym(&captures[2]))
+        });
+        let text = AN_REGEX_USER_INFO.replace_all(&text, |captures: &Captures| {
+            format!("user:info/{}", self.pseudonym(&captures[1]))
+        });
+        let text = self._anonymize_mentions(&text);
+        let text = match names {
+            Some(names) => names.replace_all(&text, |captures: &Captures| self.pseudonym(&captures[0])).into_owned(),
+            None => text,
+        };
+        if self.strip_user_ids {
+            let text = AN_REGEX_AVATAR.replace_all(&text, "");
+            AN_REGEX_USER_ID.replace_all(&text, "${1}0").into_owned()
+        } else {
+            text
+        }
+    }
+
+    /// Replaces the @mentions, except inside `[[module CSS]]` blocks and `<style>` elements, where `@` starts at-rules.
+    fn _anonymize_mentions(&self, text: &str) -> String {
+        let mut anonymized = String::with_capacity(text.len());
+        let mut start = 0;
+        for css in AN_REGEX_CSS.find_iter(text) {
+            anonymized.push_str(&self._replace_mentions(&text[start..css.start()]));
+            anonymized.push_str(css.as_str());
+            start = css.end();
+        }
+        anonymized.push_str(&self._replace_mentions(&text[start..]));
+        anonymized
+    }
+
+    fn _replace_mentions(&self, text: &str) -> String {
+        AN_REGEX_MENTION.replace_all(text, |captures: &Captures| {
+            format!("{}@{}", &captures[1], self.pseudonym(&captures[2]))
+        }).into_owned()
+    }
+
+    /// Placeholders like "(account deleted)" are kept as they are.
+    fn _pseudonym_or_placeholder(&self, name: &str) -> String {
+        if _is_placeholder(name) { name.to_string() } else { self.pseudonym(name) }
+    }
+}
+
+fn _collect_names(value: &Value, in_user: bool, names: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(object) => object.iter().for_each(|(key, child)| match child {
+            Value::String(name) if _is_username_key(key, in_user) => {
+                if !_is_placeholder(name) {
+                    names.insert(name.trim().to_string());
+                }
+            }
+            _ => _collect_names(child, _is_user_object_key(key, in_user), names),
+        }),
+        Value::Array(array) => array.iter().for_each(|item| _collect_names(item, in_user, names)),
+        _ => {}
+    }
+}
+
+fn _is_username_key(key: &str, in_user: bool) -> bool {
+    USERNAME_KEYS.contains(&key) || (in_user && USER_NAME_KEYS.contains(&key))
+}
+
+fn _is_user_object_key(key: &str, in_user: bool) -> bool {
+    USER_OBJECT_KEYS.contains(&key) || (in_user && key == "wikidotInfo")
+}
+
+/// Empty names and placeholders like "(account deleted)" are kept as they are.
+fn _is_placeholder(name: &str) -> bool {
+    name.trim().is_empty() || name.starts_with('(')
+}
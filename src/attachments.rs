@@ -0,0 +1,324 @@
+use crate::common_tools::{close_browser, download_webpage_browser, file_list, open_browser, retry_async, File, FutureIterator};
+use crate::wikidot_ajax::WikidotAjax;
+use futures_util::{FutureExt, StreamExt};
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::header::USER_AGENT;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use chrono::DateTime;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+lazy_static!(
+    static ref AT_REGEX_PAGE_ID: Regex = Regex::new(r"WIKIREQUEST\.info\.pageId\s*=\s*(\d+)").unwrap();
+    static ref AT_REGEX_BYTES: Regex = Regex::new(r"(\d[\d,.\s]*)\s*bytes").unwrap();
+    static ref AT_SEL_TR: Selector = Selector::parse("tr").unwrap();
+    static ref AT_SEL_TD: Selector = Selector::parse("td").unwrap();
+    static ref AT_SEL_LINK: Selector = Selector::parse("a[href]").unwrap();
+    static ref AT_SEL_ODATE: Selector = Selector::parse(".odate").unwrap();
+    static ref AT_SEL_PRINTUSER: Selector = Selector::parse(".printuser a").unwrap();
+);
+
+/// Wikidot ID of a page, found in the script at the top of its HTML.
+pub fn page_id(html: &str) -> Option<u64> {
+    AT_REGEX_PAGE_ID.captures(html).and_then(|captures| captures[1].parse().ok())
+}
+
+/// Lists the files of a page with Wikidot's files module, without a browser.
+pub async fn list_files_ajax(ajax: &WikidotAjax, page_id: u64) -> Result<Box<[File]>, Box<dyn Error>> {
+    let html = ajax.module_html("files/PageFilesModule", &[("page_id", page_id.to_string().as_str())]).await?;
+    Ok(file_list(&html))
+}
+
+/// Lists the files of pages with Chromium, by clicking their Files button. Fallback for [list_files_ajax].
+/// Results are in the same order as the URLs.
+pub async fn list_files_browser(urls: &[String], headless: bool, threads: usize) -> Vec<(String, Option<Box<[File]>>)> {
+    let (browser, handler) = open_browser(headless).await;
+    let browser_ref = &browser;
+
+    let files = urls.iter()
+        .map(|url| async move {
+            println!("Downloading {url} with the browser");
+            let page = download_webpage_browser(url.as_str(), browser_ref).await
+                .map(Box::new); /* Boxed because too big for the stack */
+            (url.clone(), page.map(|page| file_list(&Html::parse_document(page.as_str()))))
+        }.boxed()) /* Boxed because too big for the stack */
+        .into_future_iter()
+        .buffered(threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    close_browser((browser, handler)).await;
+    files
+}
+
+/// Lists the files of pages without a browser, then with Chromium for the pages where it failed.
+///
+/// `pages` are URLs, with the HTML of the page when it's already downloaded. Results are in the same order.
+pub async fn list_pages_files(
+    ajax: &WikidotAjax,
+    pages: Vec<(String, Option<String>)>,
+    headless: bool,
+    threads: usize,
+) -> Vec<Option<Box<[File]>>> {
+    let mut files = pages.into_iter()
+        .map(async |(url, html)| {
+            let html = match html {
+                Some(html) => Some(html),
+                None => crate::common_tools::download_webpage(url.as_str()).await,
+            };
+            let files = match html.as_deref().and_then(page_id) {
+                Some(page_id) => list_files_ajax(ajax, page_id).await
+                    .inspect_err(|e| eprintln!("[WARNING] Couldn't list the files of {url} without a browser: {e}"))
+                    .ok(),
+                None => {
+                    eprintln!("[WARNING] No page ID found for {url}.");
+                    None
+                }
+            };
+            (url, files)
+        })
+        .into_future_iter()
+        .buffered(threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let failed = files.iter()
+        .filter(|(_, files)| files.is_none())
+        .map(|(url, _)| url.clone())
+        .collect::<Box<[_]>>();
+
+    if !failed.is_empty() {
+        println!("Listing the files of {} page(s) with the browser.", failed.len());
+        let mut browser_files = list_files_browser(failed.as_ref(), headless, threads).await
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        files.iter_mut()
+            .filter(|(_, files)| files.is_none())
+            .for_each(|(url, files)| *files = browser_files.remove(url).flatten());
+    }
+
+    files.into_iter().map(|(_, files)| files).collect()
+}
+
+/// Completes the metadata of files (exact size, MIME type, uploader, upload date and absolute URL)
+/// with Wikidot's file information window. The size falls back on a HEAD request when the window can't be read.
+/// The files of all the given pages (with their unix names) are done `threads` at a time.
+pub async fn fetch_files_details<'a>(
+    ajax: &WikidotAjax,
+    site: &str,
+    pages: impl IntoIterator<Item = (&'a str, &'a mut [File])>,
+    threads: usize,
+) {
+    let client = crate::session::http_client();
+    pages.into_iter()
+        .flat_map(|(page_name, files)| files.iter_mut().map(move |file| (page_name, file)))
+        .map(|(page_name, file)| _fetch_file_details(ajax, &client, site, page_name, file))
+        .into_future_iter()
+        .buffer_unordered(threads)
+        .collect::<Vec<_>>()
+        .await;
+}
+
+async fn _fetch_file_details(ajax: &WikidotAjax, client: &reqwest::Client, site: &str, page_name: &str, file: &mut File) {
+    if file.url.is_none() {
+        file.url = AttachmentDownloader::file_url(site, page_name, &file.name).map(String::from);
+    } else {
+        file.url = file.url.as_deref()
+            .and_then(|url| Url::parse(site).and_then(|site| site.join(url)).ok())
+            .map(String::from);
+    }
+
+    let details = match file.id {
+        Some(id) => ajax.module_html("files/FileInformationWinModule", &[("file_id", id.to_string().as_str())]).await
+            .inspect_err(|e| eprintln!("[WARNING] Couldn't get the details of file {}: {e}", file.name))
+            .ok(),
+        None => None,
+    };
+    if let Some(details) = details {
+        _parse_file_details(&details, file);
+    }
+
+    if !file.size_exact && let Some(url) = file.url.as_deref() {
+        let length = client.head(crate::session::https(url))
+            .header(USER_AGENT, "ScpScriptAnthology/1.0")
+            .send().await
+            .ok()
+            .and_then(|response| response.content_length());
+        if let Some(length) = length {
+            file.size = length;
+            file.size_exact = true;
+        }
+    }
+}
+
+/// Reads the rows of the file information window, identified by their label.
+fn _parse_file_details(details: &Html, file: &mut File) {
+    for row in details.select(&AT_SEL_TR) {
+        let mut cells = row.select(&AT_SEL_TD);
+        let (Some(label), Some(value)) = (cells.next(), cells.next()) else {
+            continue;
+        };
+        let label = label.text().collect::<String>().to_lowercase();
+        let text = value.text().collect::<String>().trim().to_string();
+
+        if label.contains("url") {
+            file.url = value.select(&AT_SEL_LINK).next()
+                .and_then(|link| link.attr("href"))
+                .map(String::from)
+                .or(file.url.take());
+        } else if label.contains("size") {
+            let exact_size = AT_REGEX_BYTES.captures(&text)
+                .and_then(|captures| captures[1].chars().filter(char::is_ascii_digit).collect::<String>().parse().ok());
+            if let Some(size) = exact_size {
+                file.size = size;
+                file.size_exact = true;
+            }
+        } else if label.contains("mime") {
+            file.mime_type = Some(text);
+        } else if label.contains("uploaded by") || label.contains("uploader") {
+            file.uploader = value.select(&AT_SEL_PRINTUSER).last()
+                .map(|user| user.text().collect::<String>())
+                .or(Some(text));
+        } else if label.contains("date") {
+            let timestamp = value.select(&AT_SEL_ODATE).next()
+                .and_then(|odate| odate.value().classes().find_map(|class| class.strip_prefix("time_")))
+                .and_then(|timestamp| timestamp.parse::<i64>().ok())
+                .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+            file.upload_date = Some(timestamp.map(|date| date.to_rfc3339()).unwrap_or(text));
+        }
+    }
+}
+
+/// Checksums of the files already downloaded, in the format of `sha256sum`.
+const MANIFEST_NAME: &str = "SHA256SUMS";
+
+/// Downloads page attachments into a folder, one subfolder per page.
+///
+/// A file already present is not downloaded again if its hash still matches the one recorded
+/// in the folder's manifest during a previous run.
+#[derive(Debug)]
+pub struct AttachmentDownloader {
+    client: reqwest::Client,
+    site: String,
+    folder: PathBuf,
+    manifest: BTreeMap<String, String>,
+}
+
+impl AttachmentDownloader {
+    pub fn new(site: &str, folder: &Path) -> Self {
+        if !folder.is_dir() {
+            panic!("--download-files: path given isn't a folder path or it doesn't exist.");
+        }
+        let manifest = fs::read_to_string(folder.join(MANIFEST_NAME))
+            .map(|manifest| {
+                manifest.lines()
+                    .filter_map(|line| line.split_once("  "))
+                    .map(|(hash, path)| (path.to_string(), hash.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            client: crate::session::http_client(),
+            site: site.to_string(),
+            folder: folder.to_path_buf(),
+            manifest,
+        }
+    }
+
+    /// URL of an attachment: `/local--files/<page>/<name>`.
+    pub fn file_url(site: &str, page_name: &str, file_name: &str) -> Option<Url> {
+        let mut url = Url::parse(site).ok()?;
+        url.path_segments_mut().ok()?
+            .pop_if_empty()
+            .extend(["local--files", page_name, file_name]);
+        Some(url)
+    }
+
+    /// Downloads the files of a page, filling in their hash and length. Returns the new manifest entries.
+    pub async fn download_page_files(&self, page_name: &str, files: &mut [File]) -> Vec<(String, String)> {
+        let page_folder = self.folder.join(page_name);
+        if let Err(e) = fs::create_dir_all(&page_folder) {
+            eprintln!("Could not create folder {}: {e}", page_folder.display());
+            return vec![];
+        }
+
+        let mut entries = vec![];
+        for file in files {
+            let relative_path = format!("{page_name}/{}", file.name);
+            let path = page_folder.join(&file.name);
+
+            let (bytes, hash) = match self._already_downloaded(&relative_path, &path) {
+                Some(known) => known,
+                None => match self._download(page_name, &file.name, &path).await {
+                    Some(downloaded) => downloaded,
+                    None => continue,
+                },
+            };
+
+            file.sha256 = Some(hash.clone());
+            file.size = bytes;
+            file.size_exact = true;
+            entries.push((relative_path, hash));
+        }
+        entries
+    }
+
+    fn _already_downloaded(&self, relative_path: &str, path: &Path) -> Option<(u64, String)> {
+        let known_hash = self.manifest.get(relative_path)?;
+        let content = fs::read(path).ok()?;
+        let hash = sha256(&content);
+        (&hash == known_hash).then_some((content.len() as u64, hash))
+    }
+
+    async fn _download(&self, page_name: &str, file_name: &str, path: &Path) -> Option<(u64, String)> {
+        let url = Self::file_url(self.site.as_str(), page_name, file_name)?;
+        println!("Downloading {url}");
+
+        let content = retry_async(5, Some(Duration::from_secs(5)), async || {
+            self.client
+                .get(url.clone())
+                .header(USER_AGENT, "ScpScriptAnthology/1.0")
+                .send().await
+                .and_then(|response| response.error_for_status())
+                .inspect_err(|e| eprintln!("Download error: {e}. Retrying in 5 seconds."))?
+                .bytes().await
+                .inspect_err(|e| eprintln!("Download error: {e}. Retrying in 5 seconds."))
+        }).await
+            .inspect_err(|_| eprintln!("Too many failures, giving up on {url}."))
+            .ok()?;
+
+        fs::write(path, &content)
+            .inspect_err(|e| eprintln!("Could not write file {}: {e}", path.display()))
+            .ok()?;
+
+        Some((content.len() as u64, sha256(&content)))
+    }
+
+    /// Writes the manifest, keeping the entries of files that weren't listed this time.
+    pub fn save_manifest(&self, entries: impl IntoIterator<Item = (String, String)>) {
+        self.save_manifest_retaining(entries, |_| true);
+    }
+
+    /// Writes the manifest, keeping only the previous entries whose path is accepted by `keep`.
+    pub fn save_manifest_retaining(&self, entries: impl IntoIterator<Item = (String, String)>, keep: impl Fn(&str) -> bool) {
+        let mut manifest = self.manifest.clone();
+        manifest.retain(|path, _| keep(path));
+        manifest.extend(entries);
+        let content = manifest.iter()
+            .map(|(path, hash)| format!("{hash}  {path}\n"))
+            .collect::<String>();
+        fs::write(self.folder.join(MANIFEST_NAME), content)
+            .unwrap_or_else(|e| eprintln!("Could not write the checksums file: {e}"));
+    }
+}
+
+/// Lowercase hexadecimal SHA-256, as in SHA256SUMS files.
+pub(crate) fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|byte| format!("{byte:02x}")).collect()
+}
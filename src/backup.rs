@@ -0,0 +1,362 @@
+use crate::attachments;
+use crate::attachments::AttachmentDownloader;
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::FutureIterator;
+use crate::forum_dl;
+use crate::forum_dl::{Category, MessageFormat};
+use crate::list_pages::{select_pages, PageSelection};
+use crate::page_history;
+use crate::wikidot_ajax::WikidotAjax;
+use chrono::Utc;
+use clap::Parser;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const FORUM_NAME: &str = "forum.json";
+/// The revision count changes with every edit, including file uploads, which the source alone doesn't show.
+const PAGE_INFO: [&str; 9] = [
+    "url",
+    "wikidotInfo.wikidotId",
+    "wikidotInfo.title",
+    "wikidotInfo.tags",
+    "wikidotInfo.rating",
+    "wikidotInfo.createdAt",
+    "wikidotInfo.createdBy.name",
+    "wikidotInfo.revisionCount",
+    "wikidotInfo.source",
+];
+const LAYOUT: [(&str, &str); 7] = [
+    (MANIFEST_NAME, "This file: the site, the date of each backup and what was saved for each page."),
+    ("pages/<page>/source.txt", "Source of the page, with the sources of its fragments."),
+    ("pages/<page>/metadata.json", "Metadata of the page from Crom."),
+    ("pages/<page>/revisions.json", "Revision list of the page, oldest first."),
+    ("pages/<page>/files.json", "Details of the files attached to the page."),
+    ("files/<page>/<file>", "Files attached to the page, with their checksums in files/SHA256SUMS."),
+    (FORUM_NAME, "Categories, threads and posts of the forum, as written by forum-dl."),
+];
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct BackupParameters {
+    /// Folder of the archive. Created if it doesn't exist.
+    folder: String,
+    /// Only downloads again the pages whose source or metadata changed since the backup already in the folder,
+    /// and the forum categories with new posts.
+    #[arg(long, default_value = "false")]
+    incremental: bool,
+    /// Sets the path to the forum, if it differs from the default parameters of Wikidot. Without "/" at the start.
+    #[arg(long, default_value = "forum:start")]
+    forum_path: String,
+    /// Removes the folders of the pages gone from the site since the previous backup of the same site in the folder.
+    /// Without it, they're kept and marked as deleted in the manifest.
+    #[arg(long, default_value = "false")]
+    prune: bool,
+    /// Doesn't back up the forum.
+    #[arg(long, default_value = "false")]
+    no_forum: bool,
+    /// Format of the forum messages' content.
+    #[arg(value_enum, long, default_value = "html", ignore_case = true)]
+    message_format: MessageFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    site: String,
+    created_at: String,
+    updated_at: String,
+    layout: BTreeMap<String, String>,
+    pages: BTreeMap<String, PageEntry>,
+    forum: Option<ForumEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct PageEntry {
+    url: String,
+    title: String,
+    source_sha256: String,
+    metadata_sha256: String,
+    files: usize,
+    revisions: usize,
+    backed_up_at: String,
+    /// When the page was first found missing from the site. Its folders are kept unless --prune is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ForumEntry {
+    categories: usize,
+    threads: i32,
+    posts: i32,
+    backed_up_at: String,
+}
+
+/// What happened to a page during the backup, written on the output.
+#[derive(Serialize)]
+struct PageStatus {
+    page: String,
+    status: &'static str,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::Backup(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+    let folder = Path::new(&params.folder);
+    fs::create_dir_all(folder.join("pages"))
+        .and_then(|_| fs::create_dir_all(folder.join("files")))
+        .unwrap_or_else(|e| panic!("Could not create the archive folder {}: {e}", folder.display()));
+
+    /* The previous backup of the same site: its pages are reused with --incremental, and its deleted pages are kept */
+    let stored = fs::read_to_string(folder.join(MANIFEST_NAME)).ok()
+        .map(|manifest| serde_json::from_str::<Manifest>(&manifest)
+            .unwrap_or_else(|e| panic!("Manifest of {} is corrupted: {e}", folder.display())))
+        .filter(|manifest| manifest.site == site);
+    let previous = stored.as_ref().filter(|_| params.incremental);
+    if params.incremental && previous.is_none() {
+        println!("No previous backup of {site} in {}, backing up everything.", folder.display());
+    }
+    let now = Utc::now().to_rfc3339();
+
+    let pages = select_pages(&script_data, &PageSelection::default(), &PAGE_INFO).await;
+
+    let ajax = WikidotAjax::new(site.as_str(), script_data.verbose);
+    let downloader = AttachmentDownloader::new(site.as_str(), &folder.join("files"));
+    let previous_pages = previous.as_ref().map(|manifest| &manifest.pages);
+
+    let results = pages.iter()
+        .map(|page| _backup_page(&ajax, &downloader, folder, site.as_str(), page, previous_pages, now.as_str()))
+        .into_future_iter()
+        .buffered(script_data.threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let listed = pages.iter()
+        .filter_map(|page| page.get("url").and_then(Value::as_str))
+        .map(_page_name)
+        .collect::<BTreeSet<_>>();
+    let mut statuses = vec![];
+    let mut manifest_pages = BTreeMap::new();
+    let mut checksums = vec![];
+    /* Pages whose files weren't downloaded again keep their previous checksums */
+    let mut kept = BTreeSet::new();
+    for (page, result) in pages.iter().zip(results) {
+        match result {
+            Some((name, entry, status, files_checksums)) => {
+                if status == "unchanged" {
+                    kept.insert(name.clone());
+                }
+                statuses.push(PageStatus { page: name.clone(), status });
+                manifest_pages.insert(name, entry);
+                checksums.extend(files_checksums);
+            }
+            None => {
+                /* A failure keeps what the previous backup has of the page, to try again next time */
+                let Some(name) = page.get("url").and_then(Value::as_str).map(_page_name) else {
+                    continue;
+                };
+                if let Some(entry) = stored.as_ref().and_then(|manifest| manifest.pages.get(&name)) {
+                    manifest_pages.insert(name.clone(), entry.clone());
+                    kept.insert(name.clone());
+                }
+                statuses.push(PageStatus { page: name, status: "failed" });
+            }
+        }
+    }
+
+    /* Pages gone from the site since the previous backup. An empty listing is more likely a Crom outage than an empty site. */
+    let gone = stored.iter()
+        .flat_map(|manifest| &manifest.pages)
+        .filter(|(name, _)| !listed.contains(*name))
+        .collect::<Vec<_>>();
+    let prune = params.prune && !listed.is_empty();
+    if params.prune && listed.is_empty() {
+        eprintln!("[WARNING] Crom listed no page of {site}: nothing is pruned.");
+    }
+    for (name, entry) in gone {
+        if prune {
+            for path in [folder.join("pages").join(name), folder.join("files").join(name)] {
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                        .unwrap_or_else(|e| eprintln!("Could not remove folder {}: {e}", path.display()));
+                }
+            }
+            statuses.push(PageStatus { page: name.clone(), status: "pruned" });
+            continue;
+        }
+        if entry.deleted_at.is_none() {
+            statuses.push(PageStatus { page: name.clone(), status: "deleted" });
+        }
+        let mut entry = entry.clone();
+        entry.deleted_at.get_or_insert_with(|| now.clone());
+        manifest_pages.insert(name.clone(), entry);
+        kept.insert(name.clone());
+    }
+    downloader.save_manifest_retaining(checksums, |path| path.split_once('/').is_some_and(|(page, _)| kept.contains(page)));
+
+    let forum = if params.no_forum {
+        None
+    } else {
+        Some(_backup_forum(&script_data, params, folder, site.as_str(), previous.is_some(), now.as_str()).await)
+    };
+
+    let manifest = Manifest {
+        site: site.clone(),
+        created_at: stored.as_ref().map(|manifest| manifest.created_at.clone()).unwrap_or_else(|| now.clone()),
+        updated_at: now,
+        layout: LAYOUT.iter().map(|(path, description)| (path.to_string(), description.to_string())).collect(),
+        pages: manifest_pages,
+        forum,
+    };
+    fs::write(folder.join(MANIFEST_NAME), serde_json::to_string_pretty(&manifest).unwrap())
+        .unwrap_or_else(|e| panic!("Could not write the manifest: {e}"));
+
+    let count = |status| statuses.iter().filter(|page| page.status == status).count();
+    println!(
+        "Backup of {} page(s) written in {}: {} new, {} updated, {} unchanged, {} deleted, {} pruned, {} failed.",
+        manifest.pages.len(),
+        folder.display(),
+        count("new"),
+        count("updated"),
+        count("unchanged"),
+        count("deleted"),
+        count("pruned"),
+        count("failed"),
+    );
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, statuses.as_ref());
+    println!("Results written in file {}", path);
+}
+
+/// Saves a page in the archive, unless it didn't change since the previous backup.
+/// Returns its name, its manifest entry, its status and the checksums of its files.
+async fn _backup_page(
+    ajax: &WikidotAjax,
+    downloader: &AttachmentDownloader,
+    folder: &Path,
+    site: &str,
+    page: &Value,
+    previous: Option<&BTreeMap<String, PageEntry>>,
+    now: &str,
+) -> Option<(String, PageEntry, &'static str, Vec<(String, String)>)> {
+    let url = page.get("url").and_then(Value::as_str)?;
+    let name = _page_name(url);
+    let page_folder = folder.join("pages").join(&name);
+
+    let source = page.pointer("/wikidotInfo/source").and_then(Value::as_str).unwrap_or_default();
+    let mut metadata = page.clone();
+    if let Some(info) = metadata.get_mut("wikidotInfo").and_then(Value::as_object_mut) {
+        info.remove("source");
+    }
+    let source_sha256 = attachments::sha256(source.as_bytes());
+    let metadata_sha256 = attachments::sha256(metadata.to_string().as_bytes());
+
+    let previous_entry = previous.and_then(|pages| pages.get(&name));
+    if let Some(entry) = previous_entry
+        && entry.source_sha256 == source_sha256
+        && entry.metadata_sha256 == metadata_sha256
+        && page_folder.is_dir() {
+        let entry = PageEntry { deleted_at: None, ..entry.clone() };
+        return Some((name, entry, "unchanged", vec![]));
+    }
+
+    println!("Backing up {url}");
+    /* Any step failing leaves the page as the previous backup has it, to be tried again next time */
+    let Some(page_id) = common_tools::download_webpage(url).await.as_deref().and_then(attachments::page_id) else {
+        eprintln!("[WARNING] No page ID found for {url}, skipping it.");
+        return None;
+    };
+    let mut files = attachments::list_files_ajax(ajax, page_id).await
+        .inspect_err(|e| eprintln!("[WARNING] Couldn't list the files of {url}, skipping it: {e}"))
+        .ok()?;
+    let revisions = page_history::revisions(ajax, page_id).await
+        .inspect_err(|e| eprintln!("[WARNING] Couldn't get the history of {url}, skipping it: {e}"))
+        .ok()?;
+    attachments::fetch_files_details(ajax, site, [(name.as_str(), files.as_mut())], 1).await;
+    let checksums = downloader.download_page_files(&name, &mut files).await;
+    if files.iter().any(|file| file.sha256.is_none()) {
+        eprintln!("[WARNING] Some files of {url} couldn't be downloaded, skipping it.");
+        return None;
+    }
+
+    if let Err(e) = fs::create_dir_all(&page_folder) {
+        eprintln!("[WARNING] Could not create folder {}, skipping {url}: {e}", page_folder.display());
+        return None;
+    }
+    _write_json(&page_folder.join("metadata.json"), &metadata);
+    fs::write(page_folder.join("source.txt"), source)
+        .unwrap_or_else(|e| eprintln!("Could not write the source of {url}: {e}"));
+    _write_json(&page_folder.join("files.json"), &files);
+    _write_json(&page_folder.join("revisions.json"), &revisions);
+
+    let entry = PageEntry {
+        url: url.to_string(),
+        title: page.pointer("/wikidotInfo/title").and_then(Value::as_str).unwrap_or_default().to_string(),
+        source_sha256,
+        metadata_sha256,
+        files: files.len(),
+        revisions: revisions.len(),
+        backed_up_at: now.to_string(),
+        deleted_at: None,
+    };
+    Some((name, entry, if previous_entry.is_some() { "updated" } else { "new" }, checksums))
+}
+
+fn _page_name(url: &str) -> String {
+    url.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string()
+}
+
+/// Saves the forum, reusing from the previous backup the categories whose number of posts didn't change.
+async fn _backup_forum(script_data: &Cli, params: &BackupParameters, folder: &Path, site: &str, incremental: bool, now: &str) -> ForumEntry {
+    let path = folder.join(FORUM_NAME);
+    let mut previous = if incremental {
+        fs::read_to_string(&path).ok()
+            .and_then(|forum| serde_json::from_str::<Vec<Category>>(&forum)
+                .inspect_err(|e| eprintln!("[WARNING] Previous {FORUM_NAME} can't be read, downloading the whole forum: {e}"))
+                .ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|category| (category.url.clone(), category))
+            .collect::<BTreeMap<_, _>>()
+    } else {
+        BTreeMap::new()
+    };
+
+    let categories = forum_dl::list_categories(site, &(site.to_string() + params.forum_path.as_str())).await;
+    let order = categories.iter().map(|category| category.url.clone()).collect::<Vec<_>>();
+    let (unchanged, changed): (Vec<_>, Vec<_>) = categories.into_iter()
+        .partition(|category| category.posts.is_some()
+            && previous.get(&category.url).is_some_and(|previous| previous.posts == category.posts));
+    println!("{} forum categories unchanged, {} to download.", unchanged.len(), changed.len());
+
+    let mut by_url = unchanged.into_iter()
+        .filter_map(|category| previous.remove_entry(&category.url))
+        .collect::<BTreeMap<_, _>>();
+    by_url.extend(
+        forum_dl::download_categories(changed, site, script_data.verbose, script_data.threads, params.message_format, false).await
+            .into_iter()
+            .map(|category| (category.url.clone(), category)),
+    );
+    let categories = order.iter().filter_map(|url| by_url.remove(url)).collect::<Vec<_>>();
+    _write_json(&path, &categories);
+
+    ForumEntry {
+        categories: categories.len(),
+        threads: categories.iter().filter_map(|category| category.threads_nb).sum(),
+        posts: categories.iter().filter_map(|category| category.posts).sum(),
+        backed_up_at: now.to_string(),
+    }
+}
+
+fn _write_json<T: Serialize + ?Sized>(path: &Path, data: &T) {
+    fs::write(path, serde_json::to_string_pretty(data).unwrap())
+        .unwrap_or_else(|e| eprintln!("Could not write file {}: {e}", path.display()));
+}
@@ -0,0 +1,407 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::FutureIterator;
+use crate::list_pages::{select_pages, PageSelection};
+use crate::wikidot_source::external_links;
+use clap::Parser;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use reqwest::header::{LOCATION, USER_AGENT};
+use reqwest::redirect::Policy;
+use reqwest::{Method, StatusCode, Url};
+use scraper::{Html, Selector};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Redirections followed before giving up on a link.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct CheckLinksParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Reads the links in the HTML of the pages instead of their sources, to include the ones added by components.
+    #[arg(long, default_value = "false")]
+    from_html: bool,
+    /// Maximum number of requests per second, all websites together.
+    #[arg(long, default_value = "5")]
+    rate: f64,
+    /// Seconds before a website that doesn't answer is considered down.
+    #[arg(long, default_value = "15")]
+    timeout: u64,
+    /// Only outputs the broken links, and the pages that have some.
+    #[arg(long, default_value = "false")]
+    broken_only: bool,
+    /// Doesn't look for snapshots of the broken links on the Wayback Machine.
+    #[arg(long, default_value = "false")]
+    no_archive: bool,
+    /// Availability API of the Wayback Machine, e.g. to point the script at a local stand-in server.
+    #[arg(long, value_name = "URL", default_value = "https://archive.org/wayback/available")]
+    wayback_api: String,
+}
+
+lazy_static!(
+    static ref CL_SEL_LINK: Selector = Selector::parse("#page-content a[href], #page-content img[src]").unwrap();
+);
+
+#[derive(Serialize)]
+struct PageLinks {
+    url: String,
+    title: String,
+    links: Vec<LinkStatus>,
+}
+
+#[derive(Serialize, Clone)]
+struct LinkStatus {
+    url: String,
+    status: Option<u16>,
+    error: Option<String>,
+    /// Successive locations the link redirected to.
+    redirects: Vec<String>,
+    broken: bool,
+    /// Closest snapshot of a broken link on the Wayback Machine.
+    archive: Option<String>,
+}
+
+/// Spaces the requests of all the tasks so that no more than `rate` start per second.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate.max(0.01)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+struct LinkChecker {
+    /// Without the Wikidot session: its cookies have nothing to do on other websites.
+    client: reqwest::Client,
+    limiter: RateLimiter,
+    wayback_api: Option<String>,
+    verbose: bool,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::CheckLinks(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+
+    let info: &[&str] = if params.from_html {
+        &["url", "wikidotInfo.title", "wikidotInfo.createdAt"]
+    } else {
+        &["url", "wikidotInfo.title", "wikidotInfo.createdAt", "wikidotInfo.source"]
+    };
+    let pages = select_pages(&script_data, &params.selection, info).await;
+
+    let links = pages.iter()
+        .map(|page| _page_links(page, site.as_str(), params.from_html))
+        .into_future_iter()
+        .buffered(script_data.threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    /* Each link is checked once, however many pages use it; snapshots are looked for around the creation of the first one */
+    let mut dates = BTreeMap::new();
+    for (page, links) in pages.iter().zip(&links) {
+        for link in links {
+            dates.entry(link.as_str()).or_insert_with(|| _str(page, "/wikidotInfo/createdAt"));
+        }
+    }
+    println!("Checking {} link(s) found in {} page(s).", dates.len(), pages.len());
+
+    let checker = LinkChecker {
+        client: reqwest::Client::builder()
+            .redirect(Policy::none())
+            .timeout(Duration::from_secs(params.timeout))
+            .build()
+            .expect("Failed to build the HTTP client"),
+        limiter: RateLimiter::new(params.rate),
+        wayback_api: (!params.no_archive).then(|| params.wayback_api.clone()),
+        verbose: script_data.verbose,
+    };
+    let statuses = dates.iter()
+        .map(|(url, date)| checker.check(url, date))
+        .into_future_iter()
+        .buffer_unordered(script_data.threads)
+        .map(|status| (status.url.clone(), status))
+        .collect::<BTreeMap<_, _>>()
+        .await;
+
+    let results = pages.iter().zip(links)
+        .map(|(page, links)| PageLinks {
+            url: _str(page, "/url").to_string(),
+            title: _str(page, "/wikidotInfo/title").to_string(),
+            links: links.iter()
+                .filter_map(|link| statuses.get(link))
+                .filter(|status| !params.broken_only || status.broken)
+                .cloned()
+                .collect(),
+        })
+        .filter(|page| !params.broken_only || !page.links.is_empty())
+        .collect::<Vec<_>>();
+
+    let broken = statuses.values().filter(|status| status.broken).count();
+    println!(
+        "{broken} broken link(s) out of {}, {} with a snapshot on the Wayback Machine.",
+        statuses.len(),
+        statuses.values().filter(|status| status.archive.is_some()).count(),
+    );
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, results.as_ref());
+    println!("Results written in file {}", path);
+}
+
+fn _str<'a>(page: &'a Value, pointer: &str) -> &'a str {
+    page.pointer(pointer).and_then(Value::as_str).unwrap_or_default()
+}
+
+async fn _page_links(page: &Value, site: &str, from_html: bool) -> BTreeSet<String> {
+    if !from_html {
+        return external_links(_str(page, "/wikidotInfo/source"), site);
+    }
+
+    let url = _str(page, "/url");
+    let Some(html) = common_tools::download_webpage(url).await else {
+        eprintln!("[WARNING] Couldn't download {url}, its links are missing.");
+        return BTreeSet::new();
+    };
+    /* Attributes are joined so that the filtering of the source applies */
+    let targets = Html::parse_document(&html).select(&CL_SEL_LINK)
+        .filter_map(|link| link.attr("href").or(link.attr("src")))
+        .collect::<Box<[_]>>()
+        .join(" ");
+    external_links(&targets, site)
+}
+
+impl LinkChecker {
+    async fn check(&self, url: &str, date: &str) -> LinkStatus {
+        if self.verbose {
+            println!("Checking {url}");
+        }
+        /* Many servers don't implement HEAD properly, so a failed HEAD gets a second chance with GET */
+        let mut status = self._follow(url, Method::HEAD).await;
+        if status.broken {
+            status = self._follow(url, Method::GET).await;
+        }
+        if status.broken && let Some(api) = self.wayback_api.as_deref() {
+            status.archive = self._snapshot(api, url, date).await;
+        }
+        status
+    }
+
+    async fn _follow(&self, url: &str, method: Method) -> LinkStatus {
+        let mut status = LinkStatus {
+            url: url.to_string(),
+            status: None,
+            error: None,
+            redirects: vec![],
+            broken: true,
+            archive: None,
+        };
+
+        let mut current = url.to_string();
+        for _ in 0..=MAX_REDIRECTS {
+            self.limiter.wait().await;
+            let response = match self.client.request(method.clone(), &current)
+                .header(USER_AGENT, "ScpScriptAnthology/1.0")
+                .send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    status.error = Some(e.to_string());
+                    return status;
+                }
+            };
+            status.status = Some(response.status().as_u16());
+
+            let location = response.headers().get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| Url::parse(&current).and_then(|current| current.join(location)).ok());
+            match location {
+                Some(location) if response.status().is_redirection() => {
+                    current = location.to_string();
+                    status.redirects.push(current.clone());
+                }
+                _ => {
+                    status.broken = response.status().is_client_error() && response.status() != StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error();
+                    return status;
+                }
+            }
+        }
+        status.error = Some(format!("More than {MAX_REDIRECTS} redirections"));
+        status
+    }
+
+    /// Closest snapshot to the date (RFC 3339, possibly empty) from the availability API of the Wayback Machine.
+    async fn _snapshot(&self, api: &str, url: &str, date: &str) -> Option<String> {
+        let timestamp = date.get(..10).unwrap_or_default().replace('-', "");
+        let api = Url::parse_with_params(api, [("url", url), ("timestamp", timestamp.as_str())])
+            .inspect_err(|e| eprintln!("[WARNING] Invalid --wayback-api: {e}"))
+            .ok()?;
+        self.limiter.wait().await;
+        let response = self.client.get(api)
+            .header(USER_AGENT, "ScpScriptAnthology/1.0")
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .inspect_err(|e| eprintln!("[WARNING] Couldn't look for a snapshot of {url}: {e}"))
+            .ok()?
+            .json::<Value>().await
+            .ok()?;
+        response.pointer("/archived_snapshots/closest")
+            .filter(|closest| closest.get("available").and_then(Value::as_bool).unwrap_or(true))
+            .and_then(|closest| closest.get("url"))
+            .and_then(Value::as_str)
+            .map(|snapshot| snapshot.replacen("http://", "https://", 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Stand-in for the websites and the Wayback Machine, answering on a local port.
+    async fn _server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buffer = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let mut words = request.split_whitespace();
+                    let (method, path) = (words.next().unwrap(), words.next().unwrap());
+                    let (status, location, body) = _route(method, path);
+                    let location = location.map(|location| format!("Location: {location}\r\n")).unwrap_or_default();
+                    let body = if method == "HEAD" { String::new() } else { body };
+                    let response = format!("HTTP/1.1 {status}\r\n{location}Content-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        address
+    }
+
+    fn _route(method: &str, path: &str) -> (&'static str, Option<String>, String) {
+        match path {
+            "/ok" => ("200 OK", None, "ok".to_string()),
+            "/missing" => ("404 Not Found", None, String::new()),
+            "/limited" => ("429 Too Many Requests", None, String::new()),
+            "/error" => ("500 Internal Server Error", None, String::new()),
+            "/loop" => ("302 Found", Some("/loop".to_string()), String::new()),
+            "/get-only" if method == "HEAD" => ("405 Method Not Allowed", None, String::new()),
+            "/get-only" => ("200 OK", None, "ok".to_string()),
+            _ if path.starts_with("/redirect/") => match path["/redirect/".len()..].parse::<usize>().unwrap() {
+                0 => ("301 Moved Permanently", Some("/ok".to_string()), String::new()),
+                left => ("301 Moved Permanently", Some(format!("/redirect/{}", left - 1)), String::new()),
+            },
+            _ if path.starts_with("/wayback?") && path.contains("archived") => ("200 OK", None, r#"{"archived_snapshots": {"closest": {
+                "available": true, "status": "200", "timestamp": "20200101000000",
+                "url": "http://web.archive.org/web/20200101000000/http://example.com/archived"}}}"#.to_string()),
+            _ if path.starts_with("/wayback?") => ("200 OK", None, r#"{"archived_snapshots": {}}"#.to_string()),
+            _ => ("404 Not Found", None, String::new()),
+        }
+    }
+
+    fn _checker(wayback_api: Option<String>) -> LinkChecker {
+        LinkChecker {
+            client: reqwest::Client::builder().redirect(Policy::none()).build().unwrap(),
+            limiter: RateLimiter::new(1000.0),
+            wayback_api,
+            verbose: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_redirect_chains() {
+        let server = _server().await;
+        let status = _checker(None)._follow(&format!("{server}/redirect/2"), Method::GET).await;
+        assert!(!status.broken);
+        assert_eq!(status.status, Some(200));
+        assert_eq!(status.redirects, [
+            format!("{server}/redirect/1"),
+            format!("{server}/redirect/0"),
+            format!("{server}/ok"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_too_many_redirects() {
+        let server = _server().await;
+        let status = _checker(None)._follow(&format!("{server}/loop"), Method::GET).await;
+        assert!(status.broken);
+        assert_eq!(status.redirects.len(), MAX_REDIRECTS + 1);
+        assert!(status.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn tells_broken_links_apart() {
+        let server = _server().await;
+        let checker = _checker(None);
+        for (path, broken, code) in [("/ok", false, 200), ("/missing", true, 404), ("/limited", false, 429), ("/error", true, 500)] {
+            let status = checker._follow(&format!("{server}{path}"), Method::GET).await;
+            assert_eq!(status.broken, broken, "{path}");
+            assert_eq!(status.status, Some(code), "{path}");
+        }
+
+        let status = checker._follow("http://127.0.0.1:1/unreachable", Method::GET).await;
+        assert!(status.broken);
+        assert!(status.status.is_none() && status.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_get_when_head_fails() {
+        let server = _server().await;
+        let checker = _checker(None);
+        assert!(checker._follow(&format!("{server}/get-only"), Method::HEAD).await.broken);
+        let status = checker.check(&format!("{server}/get-only"), "").await;
+        assert!(!status.broken);
+        assert_eq!(status.status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn finds_snapshots_of_broken_links() {
+        let server = _server().await;
+        let checker = _checker(Some(format!("{server}/wayback")));
+        assert_eq!(
+            checker._snapshot(&format!("{server}/wayback"), "http://example.com/archived", "2020-01-01T00:00:00Z").await.as_deref(),
+            Some("https://web.archive.org/web/20200101000000/http://example.com/archived"),
+        );
+        assert_eq!(checker._snapshot(&format!("{server}/wayback"), "http://example.com/lost", "").await, None);
+
+        let status = checker.check(&format!("{server}/missing?archived"), "2020-01-01T00:00:00Z").await;
+        assert!(status.broken);
+        assert!(status.archive.is_some());
+        assert!(checker.check(&format!("{server}/ok"), "").await.archive.is_none());
+    }
+}
@@ -1,13 +1,34 @@
 #[cfg(feature = "forum-dl")]
 use crate::forum_dl;
+#[cfg(feature = "image-audit")]
+use crate::image_audit;
+#[cfg(feature = "page-history")]
+use crate::page_history;
+#[cfg(feature = "members")]
+use crate::members;
+#[cfg(feature = "watch")]
+use crate::watch;
+#[cfg(feature = "backup")]
+use crate::backup;
+#[cfg(feature = "diff")]
+use crate::diff;
+#[cfg(feature = "link-graph")]
+use crate::link_graph;
+#[cfg(feature = "check-links")]
+use crate::check_links;
+#[cfg(feature = "components")]
+use crate::components;
+#[cfg(feature = "styles-summary")]
+use crate::styles_summary;
 #[cfg(feature = "list-files")]
 use crate::list_files;
-#[cfg(feature = "list-files")]
+#[cfg(feature = "list-pages")]
 use crate::list_pages;
 use clap::Subcommand;
 use clap::{Parser, ValueEnum};
 use clio::Output;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, ValueEnum, Clone)]
 pub enum Branch {
     FR,
@@ -25,6 +46,7 @@ impl Branch {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, ValueEnum, Clone)]
 pub enum OutputFormat {
     JSON,
@@ -39,13 +61,77 @@ pub enum Script {
     /// Downloads the forum of a Wikidot wiki.
     #[cfg(feature = "forum-dl")]
     ForumDl(forum_dl::ForumDlParameters),
-    /// Lists the files of selected pages via a ListPages modules located on any Wikidot wiki (does not use Crom).
+    /// Lists the files of selected pages.
     ///
-    /// You need to have set up a page on the wiki that uses the ListPages module to list all pages
-    /// whose files you want to have listed. Put the ListPages module in a div with the class
-    /// ssa-list-pages so the script can detect it.
+    /// Pages are selected with Crom like with list-pages, read from a file, or listed by a ListPages module
+    /// on any Wikidot wiki (without Crom). For the latter, set up a page on the wiki that uses the ListPages module
+    /// to list all pages whose files you want to have listed, and put the ListPages module in a div with the class
+    /// ssa-list-files so the script can detect it.
     #[cfg(feature = "list-files")]
-    ListFiles(list_files::ListFilesParameters)
+    ListFiles(list_files::ListFilesParameters),
+    /// Checks that the images of selected pages are attributed in their licensing block.
+    ///
+    /// Reports images with no attribution, attributions for files the page doesn't have,
+    /// and images hotlinked from other websites.
+    #[cfg(feature = "image-audit")]
+    ImageAudit(image_audit::ImageAuditParameters),
+    /// Downloads the revision history of selected pages, optionally with the source of every revision.
+    #[cfg(feature = "page-history")]
+    PageHistory(page_history::PageHistoryParameters),
+    /// Lists the members of the site with their join date and role, optionally with their statistics from Crom.
+    #[cfg(feature = "members")]
+    Members(members::MembersParameters),
+    /// Polls the recent changes and forum posts of the site, and emits the new ones as JSON lines.
+    ///
+    /// Events (page_created, page_edited, tags_changed, forum_post) are written on the output
+    /// as one JSON object per line, whatever the output format, and can be passed to a command with --hook.
+    #[cfg(feature = "watch")]
+    Watch(watch::WatchParameters),
+    /// Archives the whole site in a folder: sources and metadata of the pages, their files and revision lists, and the forum.
+    ///
+    /// The folder describes its own layout in manifest.json. With --incremental, only the pages and forum categories
+    /// that changed since the backup already in the folder are downloaded again.
+    #[cfg(feature = "backup")]
+    Backup(backup::BackupParameters),
+    /// Compares two list-pages outputs or backups, and reports the pages added, deleted, renamed, retagged,
+    /// re-rated and edited between them.
+    ///
+    /// Works offline: --branch and --site aren't needed.
+    #[cfg(feature = "diff")]
+    Diff(diff::DiffParameters),
+    /// Extracts the links between selected pages, with their in and out degrees, the orphan pages
+    /// and the links to pages that don't exist.
+    ///
+    /// The graph itself can be written as an edge list, GraphML or GEXF with --graph.
+    #[cfg(feature = "link-graph")]
+    LinkGraph(link_graph::LinkGraphParameters),
+    /// Checks the links of selected pages to other websites, and suggests snapshots on the Wayback Machine
+    /// for the broken ones.
+    #[cfg(feature = "check-links")]
+    CheckLinks(check_links::CheckLinksParameters),
+    /// Lists the components ([[include]] directives) used by selected pages, with their arguments,
+    /// to know every page affected by a change of a component.
+    #[cfg(feature = "components")]
+    Components(components::ComponentsParameters),
+    /// Summarizes the themes, custom CSS, modules and div classes used by selected pages, to plan theme migrations.
+    ///
+    /// Pages can also be read from a list-pages output made with --styles.
+    #[cfg(feature = "styles-summary")]
+    StylesSummary(styles_summary::StylesSummaryParameters),
+}
+
+impl Script {
+    /// Whether the script works on a site, given by --branch or --site.
+    pub fn needs_site(&self) -> bool {
+        match self {
+            #[cfg(feature = "diff")]
+            Self::Diff(_) => false,
+            #[cfg(feature = "styles-summary")]
+            Self::StylesSummary(params) => params.needs_site(),
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -56,12 +142,12 @@ pub struct Cli {
         value_enum,
         short,
         long,
-        required_unless_present = "site",
         ignore_case = true
     )]
     pub branch: Option<Branch>,
     /// The wikidot website you want to use the script on. Don't forget "/" at the end.
-    #[arg(short, long, required_unless_present = "branch")]
+    /// Either --branch or --site is needed, except by the scripts working offline.
+    #[arg(short, long)]
     pub site: Option<String>,
     /// Prints in the console CROM queries and their responses.
     #[arg(short, long, default_value = "false")]
@@ -81,6 +167,14 @@ pub struct Cli {
     /// Number of parallel threads.
     #[arg(short = 'm', long, default_value = "4")]
     pub threads: usize,
+    /// Logs in to Wikidot with the given username, to access what only members or staff can see.
+    /// The password is read from the WIKIDOT_PASSWORD environment variable, or asked.
+    #[arg(long, value_name = "USERNAME", conflicts_with = "cookies")]
+    pub login: Option<String>,
+    /// Uses the Wikidot session (WIKIDOT_SESSION_ID cookie) of a cookie file exported from a browser
+    /// (Netscape cookies.txt format) or made of name=value lines.
+    #[arg(long, value_name = "FILE")]
+    pub cookies: Option<String>,
     #[command(subcommand)]
     pub script: Script,
 }
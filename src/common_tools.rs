@@ -1,17 +1,36 @@
-use crate::cli::{Cli, OutputFormat};
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+use crate::cli::Cli;
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "diff"))]
+use crate::cli::OutputFormat;
+#[cfg(feature = "list-pages")]
 use chromiumoxide::browser::HeadlessMode;
+#[cfg(feature = "list-pages")]
 use chromiumoxide::{Browser, BrowserConfig};
 use futures_util::future::{join_all, try_join_all, JoinAll, TryJoinAll};
-use futures_util::{FutureExt, StreamExt, TryFuture};
+#[cfg(any(feature = "forum-dl", feature = "list-files"))]
+use futures_util::FutureExt;
+#[cfg(feature = "list-pages")]
+use futures_util::StreamExt;
+use futures_util::TryFuture;
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
 use reqwest::header::USER_AGENT;
-use scraper::{ElementRef, Html, Selector};
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+use scraper::Html;
+#[cfg(feature = "list-pages")]
+use scraper::{ElementRef, Selector};
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "diff"))]
 use serde::Serialize;
+#[cfg(feature = "list-pages")]
 use std::error::Error;
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "diff"))]
+use std::io::Write;
 use std::time::Duration;
+#[cfg(feature = "list-pages")]
 use tokio::task::JoinHandle;
 use tokio_stream::Iter;
 
 
+#[cfg(feature = "list-pages")]
 /// Exctracts the main content of a Wikidot webpage
 pub fn parse_content(doc: &Html) -> Option<String> {
     let page_content_sel = Selector::parse("#page-content").unwrap();
@@ -43,49 +62,74 @@ pub fn parse_content(doc: &Html) -> Option<String> {
     )
 }
 
+#[cfg(feature = "list-pages")]
 #[derive(Debug, Serialize)]
 pub struct File {
+    /// Wikidot ID of the file, needed to get its details.
+    pub id: Option<u64>,
     pub name: String,
     pub file_type: String,
-    pub size: i32
+    pub mime_type: Option<String>,
+    /// In bytes. Approximated from the rounded size of the file list unless `size_exact`,
+    /// which the file details or the download make it.
+    pub size: u64,
+    pub size_exact: bool,
+    pub url: Option<String>,
+    pub uploader: Option<String>,
+    pub upload_date: Option<String>,
+    /// Only known once the file has been downloaded.
+    pub sha256: Option<String>,
 }
 
+#[cfg(feature = "list-pages")]
 impl File {
     pub fn parse(line: ElementRef) -> Self {
         let mut cells = line.children().filter_map(ElementRef::wrap);
-        const _GET_STR: fn(Option<ElementRef>) -> String = |cell|
-            cell
-                .and_then(|cell| cell.children().filter_map(ElementRef::wrap).next())
-                .map(|cell| cell.inner_html())
-                .unwrap_or(String::new());
+        const _FIRST_CHILD: fn(Option<ElementRef>) -> Option<ElementRef> = |cell|
+            cell.and_then(|cell| cell.children().filter_map(ElementRef::wrap).next());
+        let name_cell = _FIRST_CHILD(cells.next());
+        let type_cell = _FIRST_CHILD(cells.next());
+        let size = cells.next().as_ref()
+            .map(ElementRef::inner_html)
+            .as_deref()
+            .map(str::trim)
+            .map(_parse_file_size)
+            .unwrap_or(0.).round() as u64;
         Self {
-            name : _GET_STR(cells.next()),
-            file_type : _GET_STR(cells.next()),
-            size: cells.next().as_ref()
-                .map(ElementRef::inner_html)
-                .as_deref()
-                .map(str::trim)
-                .map(_parse_file_size)
-                .unwrap_or(0.).round() as i32,
+            id: line.value().id()
+                .and_then(|id| id.strip_prefix("file-row-"))
+                .and_then(|id| id.parse().ok()),
+            name: name_cell.map(|cell| cell.inner_html()).unwrap_or_default(),
+            file_type: type_cell.map(|cell| cell.inner_html()).unwrap_or_default(),
+            mime_type: type_cell.and_then(|cell| cell.attr("title")).map(String::from),
+            size,
+            size_exact: false,
+            url: name_cell.and_then(|cell| cell.attr("href")).map(String::from),
+            uploader: None,
+            upload_date: None,
+            sha256: None,
         }
     }
 }
 
-fn _parse_file_size(str: &str) -> f32 {
+#[cfg(feature = "list-pages")]
+/// Parses the sizes Wikidot displays, which use binary multiples ("1.5 kB" is 1536 bytes).
+fn _parse_file_size(str: &str) -> f64 {
     let Some((n, unit)) = str.split_once(" ") else {
         eprintln!("Can't split file size.");
         return 0.;
     };
 
-    let Ok(n) = n.parse::<f32>() else {
+    let Ok(n) = n.replace(',', "").parse::<f64>() else {
         eprintln!("Can't parse size: {str}.");
         return 0.;
     };
 
-    n * match unit {
-        "kB" => 1000.,
-        "MB" => 10000000.,
-        "Bytes" => 1.,
+    n * match unit.trim().to_lowercase().as_str() {
+        "bytes" | "byte" | "b" => 1.,
+        "kb" | "kib" => 1024.,
+        "mb" | "mib" => 1024. * 1024.,
+        "gb" | "gib" => 1024. * 1024. * 1024.,
         u => {
             eprintln!("Unknown unit {u}.");
             1.
@@ -93,23 +137,29 @@ fn _parse_file_size(str: &str) -> f32 {
     }
 }
 
+#[cfg(feature = "list-pages")]
 pub fn file_list(doc: &Html) -> Box<[File]> {
     let file_list_selector = Selector::parse("table.page-files tbody").unwrap();
     let Some(file_list) = doc.select(&file_list_selector).next() else {
         return Box::new([]); // No files
     };
 
-    file_list.children().filter_map(ElementRef::wrap).map(File::parse).collect()
+    file_list.children().filter_map(ElementRef::wrap)
+        .filter(|row| row.children().filter_map(ElementRef::wrap).any(|cell| cell.value().name() == "td")) // Header row
+        .map(File::parse)
+        .collect()
 }
 
+#[cfg(feature = "list-pages")]
 /// Downloads a singular webpage.
 pub(crate) async fn download_webpage(url: &str) -> Option<String> {
     /* Downloading html */
-    let client = reqwest::Client::new();
+    let client = crate::session::http_client();
+    let url = crate::session::https(url);
 
     retry_async(5, Some(Duration::from_secs(5)), async || {
         let response = client
-            .get(url)
+            .get(&url)
             .header(USER_AGENT, "ScpScriptAnthology/1.0")
             .send()
             .await
@@ -120,10 +170,12 @@ pub(crate) async fn download_webpage(url: &str) -> Option<String> {
     }).await.inspect_err(|_| eprintln!("Too many failures, giving up.")).ok()
 }
 
+#[cfg(feature = "list-pages")]
 pub async fn download_webpage_browser(url: &str, browser: &Browser) -> Option<String> {
+    let url = crate::session::https(url);
     // Put it in a closure so I can use the ? macro for readability.
     let f = async || {
-        let page = browser.new_page(url).await?;
+        let page = browser.new_page(url.as_str()).await?;
         page.evaluate("WIKIDOT.page.listeners.filesClick();").await?;
         let mut youre_taking_too_long = 30;
         loop {
@@ -135,7 +187,7 @@ pub async fn download_webpage_browser(url: &str, browser: &Browser) -> Option<St
             }
         }
         if youre_taking_too_long == 0 {
-            eprintln!("[WARNING] No action area found for {url}. Your login attempt may might have been unsuccessful.");
+            eprintln!("[WARNING] No action area found for {url}. If the files are only visible to members, check the session given with --login or --cookies.");
         }
         let html = page.wait_for_navigation().await?.content().await?;
         page.close().await?;
@@ -148,6 +200,7 @@ pub async fn download_webpage_browser(url: &str, browser: &Browser) -> Option<St
 
 
 
+#[cfg(feature = "list-pages")]
 pub async fn open_browser(headless: bool) -> (Browser, JoinHandle<()>) {
     let (browser, mut handler) = Browser::launch(
         BrowserConfig::builder()
@@ -156,6 +209,7 @@ pub async fn open_browser(headless: bool) -> (Browser, JoinHandle<()>) {
             .expect("Failed to build a Browser to get the files")
     )
         .await.expect("Failed to launch a Browser to get the files");
+    crate::session::apply_to_browser(&browser).await;
     let handler = tokio::task::spawn(async move {
         while let Some(h) = handler.next().await {
             if h.is_err() {
@@ -166,6 +220,7 @@ pub async fn open_browser(headless: bool) -> (Browser, JoinHandle<()>) {
     (browser, handler)
 }
 
+#[cfg(feature = "list-pages")]
 pub async fn close_browser((browser, handle): (Browser, JoinHandle<()>)) {
     browser.clear_cookies().await
         .inspect_err(|e| {eprintln!("[WARNING] Browser cookies clearing failed: {e}"); }).unwrap_or_default();
@@ -176,6 +231,7 @@ pub async fn close_browser((browser, handle): (Browser, JoinHandle<()>)) {
 
 
 
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
 pub fn xml_escape(s: &str) -> String {
     const ESC: [(&str, &str); 5] = [
         ("&", "&amp;"),
@@ -187,14 +243,22 @@ pub fn xml_escape(s: &str) -> String {
     ESC.into_iter().fold(s.to_string(), |acc, (source, cible)| acc.replace(source, cible))
 }
 
+#[cfg(feature = "forum-dl")]
+/// Text of an HTML fragment (e.g. what `inner_html` gives), with its entities decoded and its tags removed.
+pub fn html_text(s: &str) -> String {
+    Html::parse_fragment(s).root_element().text().collect()
+}
+
+#[cfg(any(feature = "forum-dl", feature = "list-files"))]
 pub async fn download_html(
     client: &reqwest::Client,
     url: &str,
     max_retries: usize,
 ) -> Result<Html, reqwest::Error> {
+    let url = crate::session::https(url);
     retry_async(max_retries, Some(Duration::from_secs(2)), async || {
         client
-            .get(url)
+            .get(&url)
             .header(USER_AGENT, "ScpScriptsAnthology/1.0")
             .send()
             .then(async |r| match r {
@@ -207,14 +271,21 @@ pub async fn download_html(
         .map(|s| Html::parse_document(s.as_str()))
 }
 
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
 pub fn write_out<T: Serialize>(script_data: Cli, result: &[T]) {
-    match script_data.output_format {
+    write_serialized(script_data.output, &script_data.output_format, result);
+}
+
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "diff"))]
+/// Writes any serializable data in the given output format, e.g. in a secondary output file.
+pub fn write_serialized<T: Serialize + ?Sized>(writer: impl Write, format: &OutputFormat, data: &T) {
+    match format {
         OutputFormat::JSON => {
-            serde_json::to_writer_pretty(script_data.output, &result)
+            serde_json::to_writer_pretty(writer, data)
                 .expect("Error writing into output file");
         }
         OutputFormat::YAML => {
-            serde_yaml::to_writer(script_data.output, &result)
+            serde_yaml::to_writer(writer, data)
                 .expect("Error writing into output file");
         }
     }
@@ -0,0 +1,196 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::FutureIterator;
+use crate::list_pages::{select_pages, Crom, PageSelection};
+use crate::wikidot_source::{includes, Include};
+use clap::Parser;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct ComponentsParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Only reports the given components: unix names, or ":wiki:page" for components of other wikis.
+    #[arg(long, value_name = "COMPONENT", num_args = 1..)]
+    component: Vec<String>,
+    /// Lists the components used by each page, instead of the pages using each component.
+    #[arg(long, default_value = "false")]
+    by_page: bool,
+    /// Doesn't look up the component pages on Crom (their title, revision count and parameters).
+    #[arg(long, default_value = "false")]
+    no_resolve: bool,
+}
+
+lazy_static!(
+    static ref CP_REGEX_PARAMETER: Regex = Regex::new(r"\{\$([\w\-]+)\}").unwrap();
+);
+
+#[derive(Serialize)]
+struct Component {
+    name: String,
+    url: String,
+    /// Whether Crom knows the page, when it was looked up.
+    exists: Option<bool>,
+    title: Option<String>,
+    /// Number of revisions of the component, to tell its versions apart between two runs.
+    revisions: Option<u64>,
+    /// `{$parameters}` the source of the component uses.
+    parameters: Option<BTreeSet<String>>,
+    used_by: Vec<Usage>,
+}
+
+#[derive(Serialize)]
+struct Usage {
+    url: String,
+    title: String,
+    arguments: BTreeMap<String, String>,
+    /// Arguments the component doesn't use, often misspelled or left from an older version.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unknown_arguments: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PageComponents {
+    url: String,
+    title: String,
+    components: Vec<PageInclude>,
+}
+
+#[derive(Serialize)]
+struct PageInclude {
+    component: String,
+    arguments: BTreeMap<String, String>,
+}
+
+/// What Crom knows about a component page.
+struct Resolved {
+    title: String,
+    revisions: Option<u64>,
+    parameters: BTreeSet<String>,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::Components(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+
+    let pages = select_pages(&script_data, &params.selection, &["url", "wikidotInfo.title", "wikidotInfo.source"]).await;
+    let wanted = params.component.iter().map(|component| component.to_lowercase()).collect::<BTreeSet<_>>();
+    let pages_includes = pages.iter()
+        .map(|page| includes(_str(page, "/wikidotInfo/source")).into_iter()
+            .filter(|include| wanted.is_empty() || wanted.contains(&include.name()))
+            .collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    if params.by_page {
+        let results = pages.iter().zip(pages_includes)
+            .filter(|(_, includes)| !includes.is_empty())
+            .map(|(page, includes)| PageComponents {
+                url: _str(page, "/url").to_string(),
+                title: _str(page, "/wikidotInfo/title").to_string(),
+                components: includes.into_iter()
+                    .map(|include| PageInclude { component: include.name(), arguments: include.arguments.into_iter().collect() })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        println!("{} page(s) use components.", results.len());
+
+        let path = script_data.output.path().clone();
+        common_tools::write_out(script_data, results.as_ref());
+        println!("Results written in file {}", path);
+        return;
+    }
+
+    let mut usages = BTreeMap::<String, (&Include, Vec<(&Value, &Include)>)>::new();
+    for (page, includes) in pages.iter().zip(&pages_includes) {
+        for include in includes {
+            usages.entry(include.name()).or_insert_with(|| (include, vec![])).1.push((page, include));
+        }
+    }
+    println!("{} component(s) used by the selected pages.", usages.len());
+
+    let crom = Crom::new(script_data.verbose);
+    let resolved = usages.values()
+        .map(|(include, _)| async {
+            let url = _component_url(site.as_str(), include);
+            let resolved = if params.no_resolve { None } else { _resolve(&crom, &url).await };
+            (url, resolved)
+        })
+        .into_future_iter()
+        .buffered(script_data.threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut components = usages.into_iter().zip(resolved)
+        .map(|((name, (_, users)), (url, resolved))| {
+            let known = resolved.as_ref().and_then(Option::as_ref);
+            Component {
+                used_by: users.into_iter()
+                    .map(|(page, include)| Usage {
+                        url: _str(page, "/url").to_string(),
+                        title: _str(page, "/wikidotInfo/title").to_string(),
+                        arguments: include.arguments.iter().cloned().collect(),
+                        unknown_arguments: known
+                            .map(|known| include.arguments.iter()
+                                .map(|(argument, _)| argument)
+                                .filter(|argument| !known.parameters.contains(*argument))
+                                .cloned()
+                                .collect())
+                            .unwrap_or_default(),
+                    })
+                    .collect(),
+                name,
+                url,
+                exists: resolved.as_ref().map(Option::is_some),
+                title: known.map(|known| known.title.clone()),
+                revisions: known.and_then(|known| known.revisions),
+                parameters: known.map(|known| known.parameters.clone()),
+            }
+        })
+        .collect::<Vec<_>>();
+    components.sort_by_key(|component| std::cmp::Reverse(component.used_by.len()));
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, components.as_ref());
+    println!("Results written in file {}", path);
+}
+
+fn _str<'a>(page: &'a Value, pointer: &str) -> &'a str {
+    page.pointer(pointer).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Wikis are assumed to be on wikidot.com when the component comes from another one.
+fn _component_url(site: &str, include: &Include) -> String {
+    match &include.site {
+        Some(wiki) => format!("https://{wiki}.wikidot.com/{}", include.page),
+        None => format!("{}/{}", site.trim_end_matches('/'), include.page),
+    }
+}
+
+/// None if the lookup failed, Some(None) if Crom doesn't know the page.
+async fn _resolve(crom: &Crom, url: &str) -> Option<Option<Resolved>> {
+    /* Crom knows the pages of wikidot.com by their http URL */
+    let query = format!(
+        "query {{ page(url: \"{}\") {{ wikidotInfo {{ title, revisionCount, source }} }} }}",
+        url.replacen("https://", "http://", 1).replace('\\', "\\\\").replace('"', "\\\""),
+    );
+    let response = crom.try_query(&query).await
+        .inspect_err(|e| eprintln!("[WARNING] Couldn't look up component {url} on Crom: {e}"))
+        .ok()?;
+    Some(response.pointer("/data/page/wikidotInfo")
+        .filter(|info| !info.is_null())
+        .map(|info| Resolved {
+            title: _str(info, "/title").to_string(),
+            revisions: info.get("revisionCount").and_then(Value::as_u64),
+            parameters: CP_REGEX_PARAMETER.captures_iter(_str(info, "/source"))
+                .map(|captures| captures[1].to_string())
+                .collect(),
+        }))
+}
@@ -0,0 +1,249 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use clap::Parser;
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct DiffParameters {
+    /// Older snapshot: a list-pages output (JSON or YAML) or a backup folder.
+    /// Renames are only detected if the pages have wikidotInfo.wikidotId.
+    old: String,
+    /// Newer snapshot, in the same formats.
+    new: String,
+    /// Smallest change of rating reported.
+    #[arg(long, default_value = "10")]
+    rating_threshold: f64,
+    /// Lines of context around the changes of sources.
+    #[arg(long, default_value = "3")]
+    context: usize,
+    /// Writes a readable report instead of the output format.
+    #[arg(long, default_value = "false")]
+    report: bool,
+}
+
+struct Page {
+    url: String,
+    id: Option<u64>,
+    title: String,
+    tags: BTreeSet<String>,
+    rating: Option<f64>,
+    source: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct WikiDiff {
+    added: Vec<PageRef>,
+    deleted: Vec<PageRef>,
+    renamed: Vec<Renamed>,
+    retagged: Vec<Retagged>,
+    rerated: Vec<Rerated>,
+    source_changed: Vec<SourceChange>,
+}
+
+#[derive(Serialize)]
+struct PageRef {
+    url: String,
+    title: String,
+}
+
+#[derive(Serialize)]
+struct Renamed {
+    id: u64,
+    old_url: String,
+    new_url: String,
+}
+
+#[derive(Serialize)]
+struct Retagged {
+    url: String,
+    added_tags: Vec<String>,
+    removed_tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Rerated {
+    url: String,
+    old_rating: f64,
+    new_rating: f64,
+}
+
+#[derive(Serialize)]
+struct SourceChange {
+    url: String,
+    diff: String,
+}
+
+pub async fn run(mut script_data: Cli) {
+    let Script::Diff(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+
+    let old = _load_snapshot(&params.old);
+    let new = _load_snapshot(&params.new);
+    println!("Comparing {} page(s) with {} page(s).", old.len(), new.len());
+
+    let diff = _diff(&old, &new, params);
+    println!(
+        "{} added, {} deleted, {} renamed, {} retagged, {} re-rated, {} with a new source.",
+        diff.added.len(),
+        diff.deleted.len(),
+        diff.renamed.len(),
+        diff.retagged.len(),
+        diff.rerated.len(),
+        diff.source_changed.len(),
+    );
+
+    if params.report {
+        write!(script_data.output, "{}", _report(&diff))
+            .unwrap_or_else(|e| eprintln!("Could not write the report: {e}"));
+    } else {
+        common_tools::write_serialized(&mut script_data.output, &script_data.output_format, &diff);
+    }
+    println!("Results written in file {}", script_data.output.path());
+}
+
+/// Pages of a list-pages output, or of a backup folder (recognized by its manifest.json).
+fn _load_snapshot(path: &str) -> Vec<Page> {
+    let folder = Path::new(path);
+    if folder.join("manifest.json").is_file() {
+        let manifest = fs::read_to_string(folder.join("manifest.json"))
+            .ok()
+            .and_then(|manifest| serde_json::from_str::<Value>(&manifest).ok())
+            .unwrap_or_else(|| panic!("Manifest of {path} can't be read."));
+        return manifest.get("pages").and_then(Value::as_object).into_iter()
+            .flat_map(|pages| pages.keys())
+            .filter_map(|name| {
+                let page_folder = folder.join("pages").join(name);
+                let metadata = fs::read_to_string(page_folder.join("metadata.json"))
+                    .ok()
+                    .and_then(|metadata| serde_json::from_str::<Value>(&metadata).ok())
+                    .or_else(|| {
+                        eprintln!("[WARNING] No metadata for page {name} in {path}, skipping it.");
+                        None
+                    })?;
+                let mut page = _parse_page(&metadata)?;
+                page.source = fs::read_to_string(page_folder.join("source.txt")).ok();
+                Some(page)
+            })
+            .collect();
+    }
+
+    let content = fs::read_to_string(path).unwrap_or_else(|e| panic!("Could not read {path}: {e}"));
+    /* YAML is a superset of JSON, so both outputs of list-pages are read the same way */
+    serde_yaml::from_str::<Vec<Value>>(&content)
+        .unwrap_or_else(|e| panic!("{path} isn't a list-pages output: {e}"))
+        .iter()
+        .filter_map(_parse_page)
+        .collect()
+}
+
+fn _parse_page(page: &Value) -> Option<Page> {
+    let info = page.get("wikidotInfo");
+    Some(Page {
+        url: page.get("url").and_then(Value::as_str)?.to_string(),
+        id: info.and_then(|info| info.get("wikidotId"))
+            .and_then(|id| id.as_u64().or_else(|| id.as_str().and_then(|id| id.parse().ok()))),
+        title: info.and_then(|info| info.get("title")).and_then(Value::as_str).unwrap_or_default().to_string(),
+        tags: info.and_then(|info| info.get("tags")).and_then(Value::as_array).into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        rating: info.and_then(|info| info.get("rating")).and_then(Value::as_f64),
+        source: info.and_then(|info| info.get("source")).and_then(Value::as_str).map(String::from),
+    })
+}
+
+/// Pages are matched by URL, then by ID among the remaining ones to find the renamed pages.
+fn _diff(old: &[Page], new: &[Page], params: &DiffParameters) -> WikiDiff {
+    let old_by_url = old.iter().map(|page| (page.url.as_str(), page)).collect::<BTreeMap<_, _>>();
+    let new_by_url = new.iter().map(|page| (page.url.as_str(), page)).collect::<BTreeMap<_, _>>();
+    let mut diff = WikiDiff::default();
+
+    let old_gone = old.iter()
+        .filter(|page| !new_by_url.contains_key(page.url.as_str()))
+        .filter_map(|page| page.id.map(|id| (id, page)))
+        .collect::<BTreeMap<_, _>>();
+    let mut pairs = vec![];
+    let mut renamed_ids = BTreeSet::new();
+    for page in new {
+        if let Some(old_page) = old_by_url.get(page.url.as_str()) {
+            pairs.push((*old_page, page));
+        } else if let Some(old_page) = page.id.and_then(|id| old_gone.get(&id)) {
+            diff.renamed.push(Renamed { id: page.id.unwrap(), old_url: old_page.url.clone(), new_url: page.url.clone() });
+            renamed_ids.insert(page.id.unwrap());
+            pairs.push((*old_page, page));
+        } else {
+            diff.added.push(PageRef { url: page.url.clone(), title: page.title.clone() });
+        }
+    }
+    diff.deleted = old.iter()
+        .filter(|page| !new_by_url.contains_key(page.url.as_str()))
+        .filter(|page| !page.id.is_some_and(|id| renamed_ids.contains(&id)))
+        .map(|page| PageRef { url: page.url.clone(), title: page.title.clone() })
+        .collect();
+
+    for (old_page, page) in pairs {
+        if old_page.tags != page.tags {
+            diff.retagged.push(Retagged {
+                url: page.url.clone(),
+                added_tags: page.tags.difference(&old_page.tags).cloned().collect(),
+                removed_tags: old_page.tags.difference(&page.tags).cloned().collect(),
+            });
+        }
+        if let (Some(old_rating), Some(new_rating)) = (old_page.rating, page.rating)
+            && (new_rating - old_rating).abs() >= params.rating_threshold {
+            diff.rerated.push(Rerated { url: page.url.clone(), old_rating, new_rating });
+        }
+        if let (Some(old_source), Some(source)) = (old_page.source.as_deref(), page.source.as_deref())
+            && old_source != source {
+            diff.source_changed.push(SourceChange {
+                url: page.url.clone(),
+                diff: TextDiff::from_lines(old_source, source)
+                    .unified_diff()
+                    .context_radius(params.context)
+                    .header(old_page.url.as_str(), page.url.as_str())
+                    .to_string(),
+            });
+        }
+    }
+    diff
+}
+
+fn _report(diff: &WikiDiff) -> String {
+    let mut report = String::new();
+    let mut section = |title: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            writeln!(report, "{title} ({}):", lines.len()).unwrap();
+            lines.iter().for_each(|line| writeln!(report, "  {line}").unwrap());
+            writeln!(report).unwrap();
+        }
+    };
+
+    section("Added pages", diff.added.iter().map(|page| format!("+ {} ({})", page.url, page.title)).collect());
+    section("Deleted pages", diff.deleted.iter().map(|page| format!("- {} ({})", page.url, page.title)).collect());
+    section("Renamed pages", diff.renamed.iter().map(|page| format!("{} -> {}", page.old_url, page.new_url)).collect());
+    section("Retagged pages", diff.retagged.iter()
+        .map(|page| {
+            let tags = page.added_tags.iter().map(|tag| format!("+{tag}"))
+                .chain(page.removed_tags.iter().map(|tag| format!("-{tag}")))
+                .collect::<Vec<_>>();
+            format!("{}: {}", page.url, tags.join(" "))
+        })
+        .collect());
+    section("Re-rated pages", diff.rerated.iter()
+        .map(|page| format!("{}: {} -> {} ({:+})", page.url, page.old_rating, page.new_rating, page.new_rating - page.old_rating))
+        .collect());
+    section("Source changes", diff.source_changed.iter().map(|page| page.url.clone()).collect());
+
+    diff.source_changed.iter().for_each(|page| writeln!(report, "{}", page.diff).unwrap());
+    report
+}
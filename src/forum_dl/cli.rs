@@ -1,4 +1,5 @@
-use clap::Parser;
+use crate::anonymize::AnonymizeParameters;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser)]
 #[command(version = "0.1.0")]
@@ -9,4 +10,43 @@ pub struct ForumDlParameters {
     /// Also downloads hidden threads.
     #[arg(long, short = 'H', default_value = "false")]
     pub hidden: bool,
-}
\ No newline at end of file
+    /// Format of the messages' content. Quotes and signatures are always put in separate fields.
+    #[arg(value_enum, long, default_value = "html", ignore_case = true)]
+    pub message_format: MessageFormat,
+    /// Also downloads the previous versions of edited posts (one more request per edited post and per revision).
+    #[arg(long, default_value = "false")]
+    pub post_revisions: bool,
+    /// Also writes the forum as a mail archive in the given folder: one folder per category, one mailbox per thread.
+    #[arg(long, value_name = "FOLDER", default_value = None)]
+    pub mail_archive: Option<String>,
+    /// Format of the mail archive.
+    #[arg(value_enum, long, default_value = "mbox", ignore_case = true, requires = "mail_archive")]
+    pub mail_format: MailFormat,
+    /// Also renders the forum as a static website in the given folder, to browse it offline.
+    #[arg(long, value_name = "FOLDER", default_value = None)]
+    pub html_site: Option<String>,
+    /// Also computes statistics on the forum (posts per user and per month, most active threads, response times…),
+    /// writes them in the given file and prints a summary.
+    #[arg(long, value_name = "FILE", default_value = None)]
+    pub stats: Option<String>,
+    #[command(flatten)]
+    pub anonymize: AnonymizeParameters,
+}
+
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+pub enum MessageFormat {
+    /// Inner HTML of the message, as displayed by Wikidot.
+    Html,
+    /// Plain text, without any formatting.
+    Text,
+    /// Markdown, keeping links, emphasis, lists and images.
+    Markdown,
+}
+
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+pub enum MailFormat {
+    /// One mbox file per thread.
+    Mbox,
+    /// One folder of .eml files per thread.
+    Eml,
+}
@@ -0,0 +1,231 @@
+use crate::forum_dl::cli::MessageFormat;
+use lazy_static::lazy_static;
+use scraper::{ElementRef, Html, Node, Selector};
+
+lazy_static!(
+    static ref MC_SEL_QUOTE: Selector = Selector::parse("blockquote, div.quote").unwrap();
+    static ref MC_SEL_SIGNATURE: Selector = Selector::parse(".signature").unwrap();
+);
+
+/// Content of a forum post, split between the poster's own text, the blocks they quoted and their signature.
+pub struct MessageContent {
+    pub content: String,
+    pub quotes: Box<[String]>,
+    pub signature: Option<String>,
+}
+
+impl MessageContent {
+    /// Splits the `.long` part of a post. The signature may be inside or next to the `.content` div.
+    pub fn parse(long: ElementRef, content: Option<ElementRef>, format: MessageFormat) -> Self {
+        let signature = long.select(&MC_SEL_SIGNATURE).next();
+
+        let Some(content) = content else {
+            return Self {
+                content: String::new(),
+                quotes: Box::default(),
+                signature: signature.map(|signature| format.render(signature)),
+            };
+        };
+
+        /* Only the outermost quotes: nested ones are rendered with their parent */
+        let quotes = content
+            .select(&MC_SEL_QUOTE)
+            .filter(|quote| {
+                !quote.ancestors()
+                    .take_while(|ancestor| ancestor.id() != content.id())
+                    .filter_map(ElementRef::wrap)
+                    .any(|ancestor| MC_SEL_QUOTE.matches(&ancestor))
+            })
+            .collect::<Box<[_]>>();
+
+        let own_html = quotes.iter()
+            .chain(signature.iter())
+            .fold(content.inner_html(), |acc, element| acc.replacen(&element.html(), "", 1));
+
+        Self {
+            content: format.render(Html::parse_fragment(own_html.as_str()).root_element()),
+            quotes: quotes.iter().map(|quote| format.render(*quote)).collect(),
+            signature: signature.map(|signature| format.render(signature)),
+        }
+    }
+}
+
+impl MessageFormat {
+    /// Renders the children of an element in the given format.
+    pub fn render(self, element: ElementRef) -> String {
+        match self {
+            MessageFormat::Html => element.inner_html().trim().to_string(),
+            MessageFormat::Text => _normalize(&_render_children(element, false)),
+            MessageFormat::Markdown => _normalize(&_render_children(element, true)),
+        }
+    }
+}
+
+const BLOCK_ELEMENTS: [&str; 8] = ["p", "div", "table", "tr", "ul", "ol", "dl", "dd"];
+/// Characters with a meaning in Markdown, escaped in the text of posts.
+const MARKDOWN_SPECIALS: [char; 10] = ['\\', '`', '*', '_', '[', ']', '<', '>', '~', '|'];
+/// Around preformatted blocks until `_normalize` removes them, so that it leaves their lines alone.
+const PRE_START: char = '\u{E000}';
+const PRE_END: char = '\u{E001}';
+
+fn _render_children(element: ElementRef, markdown: bool) -> String {
+    let preformatted = _in(element, &["pre"]);
+    let literal = preformatted || _in(element, &["code", "tt"]);
+    element.children().fold(String::new(), |mut out, child| {
+        match child.value() {
+            Node::Text(text) if markdown && !literal => {
+                let escaped = _escape_markdown(text, &out);
+                _push_text(&mut out, &escaped, false)
+            }
+            Node::Text(text) => _push_text(&mut out, text, preformatted),
+            Node::Element(_) => {
+                let child = ElementRef::wrap(child).unwrap();
+                out += _render_element(child, markdown).as_str();
+            }
+            _ => {}
+        }
+        out
+    })
+}
+
+/// Whether the element or one of its ancestors has one of the names.
+fn _in(element: ElementRef, names: &[&str]) -> bool {
+    [element].into_iter()
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .any(|element| names.contains(&element.value().name()))
+}
+
+/// Escapes the characters Markdown would take for markup, and the markers of headings and lists starting a line.
+fn _escape_markdown(text: &str, before: &str) -> String {
+    let mut escaped = text.chars()
+        .fold(String::new(), |mut acc, c| {
+            if MARKDOWN_SPECIALS.contains(&c) {
+                acc.push('\\');
+            }
+            acc.push(c);
+            acc
+        });
+    let line_start = before.is_empty() || before.ends_with('\n');
+    if line_start && escaped.trim_start().starts_with(['#', '-', '+', '=']) {
+        escaped.insert(escaped.len() - escaped.trim_start().len(), '\\');
+    }
+    escaped
+}
+
+/// Collapses whitespaces like a browser would, except inside `<pre>`.
+fn _push_text(out: &mut String, text: &str, preformatted: bool) {
+    if preformatted {
+        out.push_str(text);
+        return;
+    }
+    let collapsed = text.split_whitespace().collect::<Box<[_]>>().join(" ");
+    let starts_with_space = text.starts_with(char::is_whitespace);
+    let ends_with_space = text.ends_with(char::is_whitespace) && !collapsed.is_empty();
+    if starts_with_space && !out.is_empty() && !out.ends_with([' ', '\n']) {
+        out.push(' ');
+    }
+    out.push_str(collapsed.as_str());
+    if ends_with_space {
+        out.push(' ');
+    }
+}
+
+fn _render_element(element: ElementRef, markdown: bool) -> String {
+    let name = element.value().name();
+    let inner = || _render_children(element, markdown);
+    let attr = |attr| element.value().attr(attr).unwrap_or_default();
+
+    match (name, markdown) {
+        ("script" | "style", _) => String::new(),
+        ("br", true) => "\\\n".to_string(),
+        ("br", false) => "\n".to_string(),
+        ("hr", true) => "\n\n---\n\n".to_string(),
+        ("hr", false) => "\n\n".to_string(),
+        ("img", true) => format!("![{}]({})", attr("alt"), attr("src")),
+        ("img", false) => attr("alt").to_string(),
+        ("li", _) => {
+            let bullet = match element.parent().and_then(ElementRef::wrap) {
+                Some(list) if list.value().name() == "ol" => {
+                    let index = element.prev_siblings().filter_map(ElementRef::wrap).count() + 1;
+                    format!("{index}. ")
+                }
+                _ => "- ".to_string(),
+            };
+            format!("{bullet}{}\n", inner().trim())
+        }
+        ("td" | "th", _) => format!("{} | ", inner().trim()),
+        ("blockquote", true) => {
+            let quoted = _normalize(&inner()).lines().map(|line| format!("> {line}")).collect::<Box<[_]>>().join("\n");
+            format!("\n\n{quoted}\n\n")
+        }
+        ("pre", true) => format!("\n\n{PRE_START}```\n{}\n```{PRE_END}\n\n", inner().trim_matches('\n')),
+        ("pre", false) => format!("\n\n{PRE_START}{}{PRE_END}\n\n", inner().trim_matches('\n')),
+        (heading @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6"), true) => {
+            let level = heading[1..].parse::<usize>().unwrap();
+            format!("\n\n{} {}\n\n", "#".repeat(level), inner().trim())
+        }
+        ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => format!("\n\n{}\n\n", inner().trim()),
+        (block, _) if BLOCK_ELEMENTS.contains(&block) || block == "blockquote" => format!("\n\n{}\n\n", inner()),
+        (_, false) => inner(),
+        ("strong" | "b", true) => _wrap_inline(inner(), "**"),
+        ("em" | "i", true) => _wrap_inline(inner(), "*"),
+        ("s" | "strike" | "del", true) => _wrap_inline(inner(), "~~"),
+        ("code" | "tt", true) => _wrap_inline(inner(), "`"),
+        ("a", true) => {
+            let href = attr("href");
+            let text = inner();
+            if href.is_empty() || href.starts_with("javascript:") {
+                text
+            } else if text.trim().is_empty() {
+                format!("<{href}>")
+            } else {
+                format!("[{}]({href})", text.trim())
+            }
+        }
+        (_, true) => inner(),
+    }
+}
+
+/// Puts the markers inside the surrounding spaces, as Markdown requires.
+fn _wrap_inline(text: String, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text;
+    }
+    let trailing = if text.ends_with(' ') { " " } else { "" };
+    format!("{marker}{trimmed}{marker}{trailing}")
+}
+
+/// Removes trailing spaces and runs of blank lines, except in preformatted blocks.
+fn _normalize(text: &str) -> String {
+    let mut blocks = vec![];
+    for (i, segment) in text.split(PRE_START).enumerate() {
+        let (preformatted, rest) = match i {
+            0 => ("", segment),
+            _ => segment.split_once(PRE_END).unwrap_or((segment, "")),
+        };
+        blocks.push(preformatted.replace(PRE_END, ""));
+        blocks.push(_normalize_lines(rest));
+    }
+    blocks.into_iter()
+        .filter(|block| !block.is_empty())
+        .collect::<Box<[_]>>()
+        .join("\n\n")
+}
+
+fn _normalize_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .fold((String::new(), 0), |(mut acc, blank_lines), line| {
+            if line.trim().is_empty() {
+                (acc, blank_lines + 1)
+            } else {
+                if !acc.is_empty() {
+                    acc += if blank_lines > 0 { "\n\n" } else { "\n" };
+                }
+                acc += line;
+                (acc, 0)
+            }
+        })
+        .0
+}
@@ -0,0 +1,255 @@
+use crate::common_tools::{html_text, xml_escape};
+use crate::forum_dl::cli::MessageFormat;
+use crate::forum_dl::{Category, Message, Thread};
+use itertools::Itertools;
+use scraper::Html;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const THREADS_PER_PAGE: usize = 50;
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: auto; padding: 1em; }
+nav { margin-bottom: 1em; } nav a { margin-right: 1em; }
+table { border-collapse: collapse; width: 100%; } td, th { border-bottom: 1px solid #ccc; padding: .3em; text-align: left; }
+.post { border-left: 3px solid #ccc; margin: .5em 0; padding: .3em .8em; }
+.post .head { color: #555; font-size: .9em; } .post .head .title { font-weight: bold; color: #000; }
+.answers { margin-left: 1.5em; }
+.text { white-space: pre-wrap; }
+blockquote { border-left: 3px solid #eee; margin-left: 0; padding-left: 1em; color: #555; }
+.signature { color: #777; font-size: .85em; }
+.pager a, .pager span { margin-right: .5em; }";
+
+const SEARCH_SCRIPT: &str = "const input = document.getElementById('query');
+const results = document.getElementById('results');
+input.addEventListener('input', () => {
+    const words = input.value.toLowerCase().split(/\\s+/).filter(w => w.length > 0);
+    results.innerHTML = '';
+    if (words.length === 0) return;
+    SEARCH_INDEX
+        .filter(post => words.every(w => (post.t + ' ' + post.a + ' ' + post.x).toLowerCase().includes(w)))
+        .slice(0, 200)
+        .forEach(post => {
+            const li = document.createElement('li');
+            const link = document.createElement('a');
+            link.href = post.u;
+            link.textContent = post.t;
+            li.append(link, ' — ' + post.a + ', ' + post.d);
+            results.append(li);
+        });
+});";
+
+/// Renders the forum tree as a static website that can be browsed offline.
+pub fn write_html_site(folder: &Path, categories: &[Category], format: MessageFormat) {
+    let mut authors = BTreeMap::<&str, Vec<(&Thread, &Message)>>::new();
+    categories.iter()
+        .flat_map(|category| category.threads.iter())
+        .flat_map(|thread| _flatten(thread.messages.as_ref()).into_iter().map(move |message| (thread, message)))
+        .for_each(|(thread, message)| authors.entry(message.author.as_str()).or_default().push((thread, message)));
+    let author_files = _author_files(authors.keys().copied());
+
+    let mut files = vec![
+        ("style.css".to_string(), STYLE.to_string()),
+        ("search.js".to_string(), SEARCH_SCRIPT.to_string()),
+        ("index.html".to_string(), _index(categories)),
+        ("search.html".to_string(), _page("Search", "<input id=\"query\" type=\"search\" placeholder=\"Search posts…\" autofocus/>\
+            <ul id=\"results\"></ul><script src=\"search-index.js\"></script><script src=\"search.js\"></script>")),
+        ("search-index.js".to_string(), _search_index(categories, format)),
+        ("authors.html".to_string(), _authors_index(&authors, &author_files)),
+    ];
+
+    for category in categories {
+        let pages = category.threads.chunks(THREADS_PER_PAGE).collect::<Box<[_]>>();
+        for (i, threads) in pages.iter().enumerate() {
+            files.push((_category_file(category, i + 1), _category_page(category, threads, i + 1, pages.len(), &author_files)));
+        }
+        if pages.is_empty() {
+            files.push((_category_file(category, 1), _category_page(category, &[], 1, 1, &author_files)));
+        }
+        for thread in &category.threads {
+            files.push((_thread_file(thread), _thread_page(category, thread, format, &author_files)));
+        }
+    }
+
+    for (author, posts) in &authors {
+        files.push((author_files[author].clone(), _author_page(author, posts)));
+    }
+
+    files.into_iter()
+        .filter_map(|(name, content)| fs::write(folder.join(&name), content).err().map(|e| (name, e)))
+        .for_each(|(name, e)| eprintln!("Could not write file {name}: {e}"));
+}
+
+fn _flatten(messages: &[Message]) -> Vec<&Message> {
+    messages.iter()
+        .flat_map(|message| [message].into_iter().chain(_flatten(message.answers.as_ref())))
+        .collect()
+}
+
+/// Names, titles, descriptions and dates come from the HTML of the forum: they're inserted as they are, not escaped again.
+fn _page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"/><title>{text_title}</title><link rel=\"stylesheet\" href=\"style.css\"/></head>\
+        <body><nav><a href=\"index.html\">Forum</a><a href=\"authors.html\">Authors</a><a href=\"search.html\">Search</a></nav>\
+        <h1>{title}</h1>\n{body}\n</body></html>",
+        text_title = xml_escape(&html_text(title)),
+    )
+}
+
+fn _url_id(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or_default()
+}
+
+fn _category_file(category: &Category, page: usize) -> String {
+    format!("category-{}-{page}.html", _url_id(&category.url))
+}
+
+fn _thread_file(thread: &Thread) -> String {
+    format!("thread-{}.html", _url_id(&thread.url))
+}
+
+/// One file name per author, made unique if two names give the same slug.
+fn _author_files<'a>(authors: impl Iterator<Item = &'a str>) -> BTreeMap<&'a str, String> {
+    let mut used = BTreeMap::<String, usize>::new();
+    authors.map(|author| {
+        let slug = author.to_lowercase().chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect::<String>();
+        let count = used.entry(slug.clone()).or_default();
+        *count += 1;
+        let file = if *count == 1 { format!("author-{slug}.html") } else { format!("author-{slug}-{count}.html") };
+        (author, file)
+    }).collect()
+}
+
+fn _index(categories: &[Category]) -> String {
+    let rows = categories.iter()
+        .map(|category| format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+            _category_file(category, 1),
+            category.name,
+            category.threads.len(),
+            category.threads.iter().map(|thread| _flatten(thread.messages.as_ref()).len()).sum::<usize>(),
+        ))
+        .join("\n");
+    _page("Forum", &format!("<table><tr><th>Category</th><th>Threads</th><th>Posts</th></tr>\n{rows}\n</table>"))
+}
+
+fn _category_page(category: &Category, threads: &[Thread], page: usize, pages: usize, author_files: &BTreeMap<&str, String>) -> String {
+    let rows = threads.iter()
+        .map(|thread| format!(
+            "<tr><td><a href=\"{}\">{}</a><br/><small>{}</small></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            _thread_file(thread),
+            thread.title,
+            thread.description,
+            _author_link(&thread.author, author_files),
+            thread.date,
+            _flatten(thread.messages.as_ref()).len(),
+        ))
+        .join("\n");
+    let pager = (1..=pages)
+        .map(|i| if i == page {
+            format!("<span>{i}</span>")
+        } else {
+            format!("<a href=\"{}\">{i}</a>", _category_file(category, i))
+        })
+        .join("");
+    _page(&category.name, &format!(
+        "<table><tr><th>Thread</th><th>Started by</th><th>Date</th><th>Posts</th></tr>\n{rows}\n</table><div class=\"pager\">{pager}</div>"
+    ))
+}
+
+fn _author_link(author: &str, author_files: &BTreeMap<&str, String>) -> String {
+    match author_files.get(author) {
+        Some(file) => format!("<a href=\"{file}\">{author}</a>"),
+        None => author.to_string(),
+    }
+}
+
+fn _thread_page(category: &Category, thread: &Thread, format: MessageFormat, author_files: &BTreeMap<&str, String>) -> String {
+    let posts = thread.messages.iter().map(|message| _post(message, format, author_files)).join("\n");
+    _page(&thread.title, &format!(
+        "<p><a href=\"{}\">{}</a> — <a href=\"{}\">Original thread</a></p><p>{}</p>\n{posts}",
+        _category_file(category, 1),
+        category.name,
+        xml_escape(&thread.url),
+        thread.description,
+    ))
+}
+
+/// Content as HTML: Wikidot's HTML is kept as is, other formats are escaped.
+fn _content_html(content: &str, format: MessageFormat) -> String {
+    match format {
+        MessageFormat::Html => content.to_string(),
+        _ => format!("<div class=\"text\">{}</div>", xml_escape(content)),
+    }
+}
+
+fn _post(message: &Message, format: MessageFormat, author_files: &BTreeMap<&str, String>) -> String {
+    let anchor = message.id.map(|id| format!(" id=\"post-{id}\"")).unwrap_or_default();
+    let quotes = message.quotes.iter()
+        .map(|quote| format!("<blockquote>{}</blockquote>", _content_html(quote, format)))
+        .join("");
+    let signature = message.signature.as_deref()
+        .map(|signature| format!("<div class=\"signature\">{}</div>", _content_html(signature, format)))
+        .unwrap_or_default();
+    let edited = if message.edited { " (edited)" } else { "" };
+    let answers = message.answers.iter().map(|answer| _post(answer, format, author_files)).join("\n");
+    format!(
+        "<div class=\"post\"{anchor}><div class=\"head\"><span class=\"title\">{}</span> by {} — {}{edited}</div>\
+        {quotes}{}{signature}<div class=\"answers\">{answers}</div></div>",
+        message.title,
+        _author_link(&message.author, author_files),
+        message.date,
+        _content_html(&message.content, format),
+    )
+}
+
+fn _post_link(thread: &Thread, message: &Message) -> String {
+    format!("{}{}", _thread_file(thread), message.id.map(|id| format!("#post-{id}")).unwrap_or_default())
+}
+
+fn _authors_index(authors: &BTreeMap<&str, Vec<(&Thread, &Message)>>, author_files: &BTreeMap<&str, String>) -> String {
+    let rows = authors.iter()
+        .sorted_by_key(|(_, posts)| std::cmp::Reverse(posts.len()))
+        .map(|(author, posts)| format!("<tr><td>{}</td><td>{}</td></tr>", _author_link(author, author_files), posts.len()))
+        .join("\n");
+    _page("Authors", &format!("<table><tr><th>Author</th><th>Posts</th></tr>\n{rows}\n</table>"))
+}
+
+fn _author_page(author: &str, posts: &[(&Thread, &Message)]) -> String {
+    let rows = posts.iter()
+        .sorted_by_key(|(_, message)| message.timestamp)
+        .map(|(thread, message)| format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+            _post_link(thread, message),
+            thread.title,
+            message.title,
+            message.date,
+        ))
+        .join("\n");
+    _page(author, &format!("<table><tr><th>Thread</th><th>Post</th><th>Date</th></tr>\n{rows}\n</table>"))
+}
+
+/// Search index as a script rather than JSON, so the site also works from file:// URLs.
+fn _search_index(categories: &[Category], format: MessageFormat) -> String {
+    let index = categories.iter()
+        .flat_map(|category| category.threads.iter())
+        .flat_map(|thread| _flatten(thread.messages.as_ref()).into_iter().map(move |message| (thread, message)))
+        .map(|(thread, message)| {
+            let text = match format {
+                MessageFormat::Html => Html::parse_fragment(&message.content).root_element().text().collect::<String>(),
+                _ => message.content.clone(),
+            };
+            json!({
+                "t": html_text(&thread.title),
+                "a": html_text(&message.author),
+                "d": html_text(&message.date),
+                "u": _post_link(thread, message),
+                "x": text.split_whitespace().join(" "),
+            })
+        })
+        .collect::<Vec<_>>();
+    format!("const SEARCH_INDEX = {};", serde_json::to_string(&index).unwrap())
+}
@@ -0,0 +1,180 @@
+use crate::common_tools::html_text;
+use crate::forum_dl::cli::{MailFormat, MessageFormat};
+use crate::forum_dl::{Category, Message, Thread};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::DateTime;
+use itertools::Itertools;
+use std::fs;
+use std::path::Path;
+
+/// A forum post converted into an RFC 5322 message.
+struct Mail {
+    sender: String,
+    timestamp: Option<i64>,
+    text: String,
+}
+
+/// Writes every thread as a mailbox (or a folder of .eml files), in one folder per category.
+pub fn write_mail_archive(folder: &Path, site: &str, categories: &[Category], format: MailFormat, message_format: MessageFormat) {
+    let host = site
+        .split("://").last().unwrap_or(site)
+        .trim_end_matches('/');
+
+    for category in categories {
+        let category_folder = folder.join(_file_name(&category.name, &category.url));
+        fs::create_dir_all(&category_folder)
+            .unwrap_or_else(|e| panic!("Could not create folder {}: {e}", category_folder.display()));
+
+        for thread in &category.threads {
+            let mut mails = Vec::new();
+            _thread_mails(thread, thread.messages.as_ref(), host, &[], message_format, &mut mails);
+            let name = _file_name(&thread.title, &thread.url);
+
+            let result = match format {
+                MailFormat::Mbox => fs::write(
+                    category_folder.join(name + ".mbox"),
+                    mails.iter().map(_mbox_entry).join(""),
+                ),
+                MailFormat::Eml => {
+                    let thread_folder = category_folder.join(name);
+                    fs::create_dir_all(&thread_folder).and_then(|_| {
+                        mails.iter().enumerate().try_for_each(|(i, mail)| {
+                            fs::write(thread_folder.join(format!("{:04}.eml", i + 1)), mail.text.replace('\n', "\r\n"))
+                        })
+                    })
+                }
+            };
+
+            if let Err(e) = result {
+                eprintln!("Could not write the mails of thread {}: {e}", thread.url);
+            }
+        }
+    }
+}
+
+/// Readable and unique file name: the title, followed by the ID at the end of the URL.
+fn _file_name(title: &str, url: &str) -> String {
+    let slug = title.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .take(12)
+        .join("-");
+    let id = url.rsplit('/').next().unwrap_or_default();
+    format!("{slug}-{id}")
+}
+
+/// Depth-first walk of the answers tree: `references` are the Message-IDs of the ancestors.
+fn _thread_mails(thread: &Thread, messages: &[Message], host: &str, references: &[String], format: MessageFormat, mails: &mut Vec<Mail>) {
+    let mut references = references.to_vec();
+    let is_thread_root = references.is_empty();
+
+    for message in messages {
+        let message_id = match message.id {
+            Some(id) => format!("<post-{id}@{host}>"),
+            None => format!("<{}.{}@{host}>", thread.url.rsplit('/').next().unwrap_or_default(), mails.len()),
+        };
+        mails.push(_mail(thread, message, host, &message_id, &references, format));
+        _thread_mails(thread, message.answers.as_ref(), host, &[references.as_slice(), std::slice::from_ref(&message_id)].concat(), format, mails);
+
+        /* Top-level posts all answer the one that started the thread */
+        if is_thread_root && references.is_empty() {
+            references.push(message_id);
+        }
+    }
+}
+
+fn _mail(thread: &Thread, message: &Message, host: &str, message_id: &str, references: &[String], format: MessageFormat) -> Mail {
+    /* Names and titles come from the HTML of the forum */
+    let author = html_text(&message.author);
+    let local_part = author.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '-' })
+        .collect::<String>();
+    let sender = format!("{}@{host}", if local_part.is_empty() { "unknown" } else { local_part.as_str() });
+    let subject = if message.title.is_empty() {
+        format!("Re: {}", html_text(&thread.title))
+    } else {
+        html_text(&message.title)
+    };
+
+    let mut headers = vec![
+        format!("From: {} <{sender}>", _display_name(&author)),
+        format!("Subject: {}", _encode_header(&subject)),
+        format!("Message-ID: {message_id}"),
+    ];
+    if let Some(date) = message.timestamp.and_then(|ts| DateTime::from_timestamp(ts, 0)) {
+        headers.push(format!("Date: {}", date.to_rfc2822()));
+    }
+    if let Some(parent) = references.last() {
+        headers.push(format!("In-Reply-To: {parent}"));
+        headers.push(format!("References: {}", references.join(" ")));
+    }
+    headers.push(format!("X-Wikidot-Url: {}{}", thread.url, message.id.map(|id| format!("#post-{id}")).unwrap_or_default()));
+    headers.push("MIME-Version: 1.0".to_string());
+    headers.push(format!(
+        "Content-Type: text/{}; charset=utf-8",
+        if format == MessageFormat::Html { "html" } else { "plain" }
+    ));
+    headers.push("Content-Transfer-Encoding: 8bit".to_string());
+
+    Mail {
+        sender,
+        timestamp: message.timestamp,
+        text: format!("{}\n\n{}\n", headers.join("\n"), _body(message, format)),
+    }
+}
+
+fn _body(message: &Message, format: MessageFormat) -> String {
+    let quotes = message.quotes.iter().map(|quote| match format {
+        MessageFormat::Html => format!("<blockquote>{quote}</blockquote>"),
+        _ => quote.lines().map(|line| format!("> {line}").trim_end().to_string()).join("\n"),
+    });
+    let signature = message.signature.iter().map(|signature| match format {
+        MessageFormat::Html => format!("<div class=\"signature\">-- <br/>{signature}</div>"),
+        _ => format!("-- \n{signature}"),
+    });
+
+    quotes
+        .chain([message.content.clone()])
+        .chain(signature)
+        .filter(|part| !part.is_empty())
+        .join("\n\n")
+}
+
+/// mboxrd entry: "From " separator line, and body lines starting with "From " quoted.
+fn _mbox_entry(mail: &Mail) -> String {
+    let date = mail.timestamp
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .unwrap_or_default()
+        .format("%a %b %e %H:%M:%S %Y");
+    let text = mail.text.lines()
+        .map(|line| if line.trim_start_matches('>').starts_with("From ") { format!(">{line}") } else { line.to_string() })
+        .join("\n");
+    format!("From {} {date}\n{text}\n\n", mail.sender)
+}
+
+/// Display name of an address: quoted when it has characters with a meaning in addresses, encoded when it isn't ASCII.
+fn _display_name(name: &str) -> String {
+    const SPECIALS: [char; 13] = ['(', ')', '<', '>', '[', ']', ':', ';', '@', '\\', ',', '.', '"'];
+    let name = name.replace(['\r', '\n'], " ");
+    if name.is_ascii() && name.contains(SPECIALS) {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        _encode_header(&name)
+    }
+}
+
+/// RFC 2047 encoding of non-ASCII header values, in encoded-words short enough for the line length limit.
+fn _encode_header(value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    if value.is_ascii() {
+        return value;
+    }
+    value.chars()
+        .chunks(15)
+        .into_iter()
+        .map(|chunk| format!("=?UTF-8?B?{}?=", BASE64.encode(chunk.collect::<String>())))
+        .join("\n ")
+}
@@ -1,22 +1,31 @@
 mod cli;
+mod content;
+mod html_site;
+mod mail;
+mod revisions;
+mod stats;
 
 use crate::cli::{Cli, Script};
 use crate::common_tools;
 use crate::common_tools::{download_html, FutureIterator};
+use crate::forum_dl::content::MessageContent;
+use crate::forum_dl::revisions::PostRevision;
+use crate::wikidot_ajax::WikidotAjax;
 use futures_util::StreamExt;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
 use scraper::{ElementRef, Html, Selector};
-use std::iter;
-use std::sync::Arc;
 use chromiumoxide::serde_json::{Deserialize, Serialize};
 use lazy_static::lazy_static;
-pub use cli::ForumDlParameters;
+pub use cli::{ForumDlParameters, MessageFormat};
 
 #[derive(Serialize, Deserialize)]
-struct Category {
+pub struct Category {
     name: String,
-    url: String,
-    threads_nb: Option<i32>,
-    posts: Option<i32>,
+    pub url: String,
+    pub threads_nb: Option<i32>,
+    pub posts: Option<i32>,
     threads: Box<[Thread]>,
 }
 
@@ -26,6 +35,7 @@ struct Thread {
     url: String,
     description: String,
     date: String,
+    timestamp: Option<i64>,
     posts_nb: Option<i32>,
     author: String,
     messages: Box<[Message]>,
@@ -33,10 +43,16 @@ struct Thread {
 
 #[derive(Serialize, Deserialize)]
 struct Message {
+    id: Option<u64>,
     title: String,
     content: String,
+    quotes: Box<[String]>,
+    signature: Option<String>,
     author: String,
     date: String,
+    timestamp: Option<i64>,
+    edited: bool,
+    revisions: Box<[PostRevision]>,
     answers: Box<[Message]>,
 }
 
@@ -46,11 +62,11 @@ lazy_static!(
     static ref FDL_SEL_TITLE: Selector = Selector::parse("td.name div.title a").unwrap();
     static ref FDL_SEL_THREADS: Selector = Selector::parse(".threads").unwrap();
     static ref FDL_SEL_POSTS: Selector = Selector::parse(".posts").unwrap();
+    static ref FDL_REGEX_ID: Regex = Regex::new(r"/(\w)-(\d+)").unwrap();
 );
 
 pub async fn forum_dl(data: Cli) {
 
-    let client = Arc::new(reqwest::Client::new());
     let url = data.site.as_ref().unwrap();
     let forum_dl_parameters = match &data.script {
         Script::ForumDl(e) => e,
@@ -60,25 +76,79 @@ pub async fn forum_dl(data: Cli) {
         + forum_dl_parameters.forum_path.as_str()
         + if forum_dl_parameters.hidden { "/hidden/show" } else { "" };
 
-    if data.verbose {
-        eprintln!("Warning: --verbose has no effect for this script.");
+    let mail_folder = forum_dl_parameters.mail_archive.as_ref().map(Path::new);
+    if mail_folder.is_some_and(|folder| !folder.is_dir()) {
+        panic!("--mail-archive: path given isn't a folder path or it doesn't exist.");
+    }
+    let site_folder = forum_dl_parameters.html_site.as_ref().map(Path::new);
+    if site_folder.is_some_and(|folder| !folder.is_dir()) {
+        panic!("--html-site: path given isn't a folder path or it doesn't exist.");
+    }
+
+    let categories = list_categories(url, &forum_path).await;
+    let categories = download_categories(
+        categories,
+        url,
+        data.verbose,
+        data.threads,
+        forum_dl_parameters.message_format,
+        forum_dl_parameters.post_revisions,
+    ).await;
+
+    /* The anonymizer works on JSON values, so the forum tree makes a round trip through them. */
+    let categories: Box<[Category]> = match forum_dl_parameters.anonymize.anonymizer() {
+        Some(anonymizer) => {
+            let mut values = [serde_json::to_value(&categories).unwrap()];
+            anonymizer.anonymize(&mut values);
+            serde_json::from_value(values[0].take()).expect("Anonymized forum tree can't be read back (internal error).")
+        }
+        None => categories,
+    };
+
+    if let Some(folder) = mail_folder {
+        mail::write_mail_archive(folder, url, categories.as_ref(), forum_dl_parameters.mail_format, forum_dl_parameters.message_format);
+        println!("Mail archive written in folder {}", folder.display());
+    }
+
+    if let Some(folder) = site_folder {
+        html_site::write_html_site(folder, categories.as_ref(), forum_dl_parameters.message_format);
+        println!("Website written in folder {}", folder.display());
+    }
+
+    if let Some(stats_path) = forum_dl_parameters.stats.as_ref() {
+        let stats = stats::ForumStats::compute(categories.as_ref());
+        println!("{}", stats.summary());
+        let file = fs::File::create(stats_path)
+            .unwrap_or_else(|e| panic!("--stats: could not create file {stats_path}: {e}"));
+        common_tools::write_serialized(file, &data.output_format, &stats);
+        println!("Statistics written in file {stats_path}");
     }
 
-    println!("Downloading {forum_path}");
+    let path = data.output.path().clone();
+
+    common_tools::write_out(data, &categories);
+
+    println!("Results written in file {}", path);
+}
+
+/// Categories listed on the start page of a forum, without their threads.
+pub async fn list_categories(site: &str, forum_url: &str) -> Vec<Category> {
+    let client = crate::session::http_client();
+    println!("Downloading {forum_url}");
 
-    let doc = download_html(&client, forum_path.as_str(), 5)
+    let doc = download_html(&client, forum_url, 5)
         .await
         .expect("Too many failed attempts");
 
     let groups = doc.select(&FDL_SEL_GROUP);
-    let categories: Box<[_]> = groups
+    let categories: Vec<_> = groups
         .flat_map(|group| {
             group.select(&FDL_SEL_TR).skip(1).map(|tr| Category {
                 name: tr
                     .select(&FDL_SEL_TITLE)
                     .next().map(|title| title.inner_html())
                     .unwrap_or_else(|| panic!("Can't find title for a category: {}", tr.inner_html())),
-                url: url.clone() +
+                url: site.to_string() +
                     tr.select(&FDL_SEL_TITLE).next()
                     .and_then(|title| title.attr("href"))
                     .unwrap_or_else(|| panic!("Can't find title for a category: {}", tr.inner_html()))
@@ -100,22 +170,35 @@ pub async fn forum_dl(data: Cli) {
 
     println!("Categories found: {}", categories.len());
 
-    let categories = categories
+    categories
+}
+
+/// Downloads the threads and posts of categories.
+pub async fn download_categories(
+    categories: Vec<Category>,
+    url: &str,
+    verbose: bool,
+    threads: usize,
+    message_format: MessageFormat,
+    post_revisions: bool,
+) -> Box<[Category]> {
+    let forum_dl = ForumDl {
+        ajax: WikidotAjax::new(url, verbose),
+        site: url.to_string(),
+        max_threads: threads,
+        message_format,
+        post_revisions,
+    };
+
+    categories
         .into_iter()
-        .map(|category| _category_dl(client.clone(), category, url.clone(), data.threads))
+        .map(|category| forum_dl._category_dl(category))
         .into_future_iter()
         .buffer_unordered(1)
         .collect::<Vec<_>>()
-        .await.into_boxed_slice();
-
-    let path = data.output.path().clone();
-
-    common_tools::write_out(data, &categories);
-
-    println!("Results written in file {}", path);
+        .await.into_boxed_slice()
 }
 
-
 fn _get_page_nb(doc: &Html) -> i32 {
     let sel_pager = Selector::parse(".pager span").unwrap();
 
@@ -130,6 +213,21 @@ fn _get_page_nb(doc: &Html) -> i32 {
         .unwrap_or(1)
 }
 
+/// Reads the Unix timestamp Wikidot puts in the classes of its dates (`odate time_1234567890`).
+fn _odate_timestamp(odate: ElementRef) -> Option<i64> {
+    odate.value().classes()
+        .find_map(|class| class.strip_prefix("time_"))
+        .and_then(|timestamp| timestamp.parse().ok())
+}
+
+/// Extracts the numeric ID from a category (`c-123`) or thread (`t-123`) URL.
+fn _id_from_url(url: &str, prefix: char) -> String {
+    FDL_REGEX_ID.captures_iter(url)
+        .find(|captures| captures[1].starts_with(prefix))
+        .map(|captures| captures[2].to_string())
+        .unwrap_or_else(|| panic!("No ID found in URL {url}"))
+}
+
 lazy_static!(
     static ref GT_SEL_TR: Selector = Selector::parse(".table tr").unwrap();
     static ref GT_SEL_TITLE: Selector = Selector::parse(".name .title a").unwrap();
@@ -139,11 +237,7 @@ lazy_static!(
     static ref GT_SEL_AUTHOR: Selector = Selector::parse(".started .printuser a").unwrap();
 );
 
-async fn _get_threads(client: Arc<reqwest::Client>, url: String, site: String) -> Box<[Thread]> {
-    let doc = download_html(client.as_ref(), url.as_str(), 5)
-        .await
-        .expect("Too many failed attempts");
-
+fn _parse_threads(doc: &Html, site: &str) -> Box<[Thread]> {
     doc.select(&GT_SEL_TR)
         .skip(1)
         .map(|thread| {
@@ -153,7 +247,7 @@ async fn _get_threads(client: Arc<reqwest::Client>, url: String, site: String) -
                     .expect("No title for a forum thread.")
                     .trim()
                     .to_string(),
-                url: site.clone()
+                url: site.to_string()
                     + title
                         .and_then(|link| link.attr("href"))
                         .expect("No url for a forum thread")
@@ -162,21 +256,25 @@ async fn _get_threads(client: Arc<reqwest::Client>, url: String, site: String) -
                         .rsplit_once('/')
                         .unwrap()
                         .0,
-                description: doc
+                description: thread
                     .select(&GT_SEL_DESC)
                     .next().map(|desc| desc.inner_html())
                     .unwrap_or_default()
                     .trim()
                     .to_string(),
-                date: doc
+                date: thread
                     .select(&GT_SEL_DATE)
                     .next().map(|date| date.inner_html())
                     .unwrap_or_default(),
-                posts_nb: doc
+                timestamp: thread
+                    .select(&GT_SEL_DATE)
+                    .next()
+                    .and_then(_odate_timestamp),
+                posts_nb: thread
                     .select(&GT_SEL_POSTS)
                     .next()
-                    .and_then(|posts| posts.inner_html().parse().ok()),
-                author: doc
+                    .and_then(|posts| posts.inner_html().trim().parse().ok()),
+                author: thread
                     .select(&GT_SEL_AUTHOR).nth(1).map(|author| author.inner_html())
                     .unwrap_or_default(),
                 messages: Box::default(),
@@ -191,22 +289,29 @@ lazy_static!(
     static ref PM_SEL_TITLE: Selector = Selector::parse(".long .head .title").unwrap();
     static ref PM_SEL_DATE: Selector = Selector::parse(".long .head .info .odate").unwrap();
     static ref PM_SEL_AUTHOR: Selector = Selector::parse(".long .head .info .printuser a").unwrap();
+    static ref PM_SEL_LONG: Selector = Selector::parse(".long").unwrap();
     static ref PM_SEL_CONTENT: Selector = Selector::parse(".long .content").unwrap();
+    static ref PM_SEL_CHANGES: Selector = Selector::parse(".long .changes").unwrap();
 );
 
-fn _parse_messages_rec(post_container: ElementRef) -> Message {
-    let mut skip = 0;
+fn _parse_messages_rec(post_container: ElementRef, format: MessageFormat) -> Message {
+    /* A post container holds the post itself, followed by the containers of its answers. */
+    let children = || post_container.children().filter_map(ElementRef::wrap);
 
-    let message = post_container
-        .select(&PM_SEL_POST)
-        .next()
-        .or({
-            skip += 1;
-            post_container.select(&PM_SEL_CONTAINERS).nth(1)
-        })
+    let message = children()
+        .find(|child| PM_SEL_POST.matches(child))
         .expect("No post in a post container.");
 
+    let MessageContent { content, quotes, signature } = MessageContent::parse(
+        message.select(&PM_SEL_LONG).next().unwrap_or(message),
+        message.select(&PM_SEL_CONTENT).next(),
+        format,
+    );
+
     Message {
+        id: message.value().id()
+            .and_then(|id| id.strip_prefix("post-"))
+            .and_then(|id| id.parse().ok()),
         title: message
             .select(&PM_SEL_TITLE)
             .next().map(|title| title.inner_html())
@@ -217,107 +322,131 @@ fn _parse_messages_rec(post_container: ElementRef) -> Message {
             .select(&PM_SEL_DATE)
             .next().map(|title| title.inner_html())
             .unwrap_or("Unknown date".to_string()),
+        timestamp: message
+            .select(&PM_SEL_DATE)
+            .next()
+            .and_then(_odate_timestamp),
         author: message
             .select(&PM_SEL_AUTHOR).nth(1).map(|title| title.inner_html())
             .unwrap_or("(account deleted)".to_string()),
-        content: message
-            .select(&PM_SEL_CONTENT)
-            .next().map(|title| title.inner_html())
-            .unwrap_or_default()
-            .trim()
-            .to_string(),
-        answers: message
-            .select(&PM_SEL_CONTAINERS)
-            .skip(skip)
-            .map(|container| _parse_messages_rec(container))
+        content,
+        quotes,
+        signature,
+        edited: message.select(&PM_SEL_CHANGES).next().is_some(),
+        revisions: Box::default(),
+        answers: children()
+            .filter(|child| PM_SEL_CONTAINERS.matches(child))
+            .map(|container| _parse_messages_rec(container, format))
             .collect(),
     }
 }
 
-lazy_static!(
-    static ref GM_SEL_THREAD_CONTAINER_POSTS: Selector = Selector::parse("#thread-container-posts").unwrap();
-);
+/// Downloads every page of a forum and its threads through Wikidot's module connector.
+struct ForumDl {
+    ajax: WikidotAjax,
+    site: String,
+    max_threads: usize,
+    message_format: MessageFormat,
+    post_revisions: bool,
+}
 
-async fn _get_messages(client: Arc<reqwest::Client>, mut thread: Thread) -> Thread {
-    let doc = download_html(client.as_ref(), thread.url.as_str(), 5)
-        .await
-        .expect("Too many failed attempts");
-    let pages_nb = _get_page_nb(&doc);
-
-    let full_doc = Html::parse_fragment(
-        iter::once(doc)
-            .chain(
-                (1..=pages_nb)
-                    .map(|i| format!("{}/p/{i}", thread.url))
-                    .map(async |url| {
-                        download_html(client.as_ref(), url.as_str(), 5)
-                            .await
-                            .expect("Too many failed attempts")
+impl ForumDl {
+    async fn _category_dl(&self, mut category: Category) -> Category {
+        println!("Category: {}", category.name);
+        let category_id = _id_from_url(category.url.as_str(), 'c');
+
+        let first_page = self._get_threads_page(category_id.as_str(), 1).await;
+        let pages_nb = _get_page_nb(&first_page);
+        let mut threads = _parse_threads(&first_page, self.site.as_str()).into_vec();
+
+        threads.extend(
+            (2..=pages_nb)
+                .map(async |page| {
+                    let doc = self._get_threads_page(category_id.as_str(), page).await;
+                    _parse_threads(&doc, self.site.as_str())
                 })
-                .join_all().await
+                .into_future_iter()
+                .buffered(self.max_threads)
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .flatten()
+        );
+
+        println!("Threads found: {}", threads.len());
+
+        let threads = threads
+            .into_iter()
+            .map(|thread| self._get_messages(thread))
+            .into_future_iter()
+            .buffer_unordered(self.max_threads)
+            .collect::<Vec<_>>()
+            .await.into_boxed_slice();
+
+        category.threads = threads;
+
+        if category.threads_nb.is_some_and(|len| len != category.threads.len() as i32)  {
+            eprintln!(
+                "[WARNING] Number of threads found doesn't match number of threads announced by Wikidot."
             )
-            .fold(String::new(), |acc, doc| {
-                acc + doc
-                    .select(&GM_SEL_THREAD_CONTAINER_POSTS)
-                    .fold(String::new(), |acc2, thread_container| {
-                        acc2 + thread_container.inner_html().as_str() + "\n"
-                    })
-                    .as_str()
-                    + "\n"
-            })
-            .as_str(),
-    );
+        }
 
-    let messages: Box<[_]> = full_doc
-        .select(&PM_SEL_CONTAINERS)
-        .map(|post_container| _parse_messages_rec(post_container))
-        .collect();
+        category
+    }
 
-    thread.messages = messages;
+    async fn _get_threads_page(&self, category_id: &str, page: i32) -> Html {
+        self.ajax
+            .module_html("forum/ForumViewCategoryModule", &[("c", category_id), ("p", page.to_string().as_str())])
+            .await
+            .unwrap_or_else(|e| panic!("Couldn't download page {page} of category {category_id}: {e}"))
+    }
 
-    thread
-}
+    async fn _get_messages(&self, mut thread: Thread) -> Thread {
+        let thread_id = _id_from_url(thread.url.as_str(), 't');
 
-async fn _category_dl(
-    client: Arc<reqwest::Client>,
-    mut category: Category,
-    site: String,
-    max_threads: usize,
-) -> Category {
-    println!("Category: {}", category.name);
-    let doc = download_html(client.as_ref(), category.url.as_str(), 5)
-        .await
-        .expect("Too many failed attempts");
-    let pages_nb = _get_page_nb(&doc);
+        let first_page = self._get_posts_page(thread_id.as_str(), 1).await;
+        let pages_nb = _get_page_nb(&first_page);
+        let mut messages = self._parse_posts_page(&first_page).into_vec();
 
-    let threads = (1..pages_nb + 1)
-        .map(|i| format!("{}/p/{i}", category.url))
-        .map(|page| _get_threads(client.clone(), page, site.clone()))
-        .into_future_iter()
-        .buffer_unordered(max_threads)
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .flatten()
-        .collect::<Box<[_]>>();
+        /* Each page holds whole top-level posts with their answers, so they can be parsed separately. */
+        messages.extend(
+            (2..=pages_nb)
+                .map(async |page| {
+                    let doc = self._get_posts_page(thread_id.as_str(), page).await;
+                    self._parse_posts_page(&doc)
+                })
+                .into_future_iter()
+                .buffered(self.max_threads)
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .flatten()
+        );
 
-    println!("Threads found: {}", threads.len());
+        if self.post_revisions {
+            revisions::fetch_revisions(&self.ajax, messages.as_mut(), self.message_format, self.max_threads).await;
+        }
 
-    let threads = threads
-        .into_iter()
-        .map(|thread| _get_messages(client.clone(), thread))
-        .into_future_iter()
-        .buffer_unordered(max_threads)
-        .collect::<Vec<_>>()
-        .await.into_boxed_slice();
+        thread.messages = messages.into_boxed_slice();
 
-    category.threads = threads;
+        thread
+    }
 
-    if category.threads_nb.is_some_and(|len| len != category.threads.len() as i32)  {
-        eprintln!(
-            "[WARNING] Number of threads found doesn't match number of threads announced by Wikidot."
-        )
+    async fn _get_posts_page(&self, thread_id: &str, page: i32) -> Html {
+        self.ajax
+            .module_html("forum/ForumViewThreadPostsModule", &[("t", thread_id), ("pageNo", page.to_string().as_str())])
+            .await
+            .unwrap_or_else(|e| panic!("Couldn't download page {page} of thread {thread_id}: {e}"))
     }
 
-    category
-}
\ No newline at end of file
+    fn _parse_posts_page(&self, doc: &Html) -> Box<[Message]> {
+        doc.select(&PM_SEL_CONTAINERS)
+            .filter(|container| {
+                !container.ancestors()
+                    .filter_map(ElementRef::wrap)
+                    .any(|ancestor| PM_SEL_CONTAINERS.matches(&ancestor))
+            })
+            .map(|post_container| _parse_messages_rec(post_container, self.message_format))
+            .collect()
+    }
+}
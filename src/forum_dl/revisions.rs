@@ -0,0 +1,107 @@
+use crate::common_tools::FutureIterator;
+use crate::forum_dl::cli::MessageFormat;
+use crate::forum_dl::Message;
+use crate::wikidot_ajax::WikidotAjax;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A previous version of an edited forum post.
+#[derive(Serialize, Deserialize)]
+pub struct PostRevision {
+    id: u64,
+    editor: String,
+    date: String,
+    title: String,
+    content: String,
+}
+
+lazy_static!(
+    static ref PR_SEL_TR: Selector = Selector::parse("tr").unwrap();
+    static ref PR_SEL_EDITOR: Selector = Selector::parse(".printuser a").unwrap();
+    static ref PR_SEL_DATE: Selector = Selector::parse(".odate").unwrap();
+    static ref PR_SEL_SHOW: Selector = Selector::parse("a[onclick*=\"showRevision\"]").unwrap();
+    static ref PR_REGEX_ID: Regex = Regex::new(r"showRevision\(\s*event\s*,\s*(\d+)\s*\)").unwrap();
+);
+
+/// Fetches the revisions of every edited post in a message tree, at most `max_threads` requests at a time.
+pub async fn fetch_revisions(ajax: &WikidotAjax, messages: &mut [Message], format: MessageFormat, max_threads: usize) {
+    let mut edited = _edited_posts(messages);
+    let lists = edited.iter()
+        .map(|(post_id, _)| _revision_list(ajax, *post_id))
+        .into_future_iter()
+        .buffered(max_threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let revisions = lists.iter()
+        .zip(&edited)
+        .flat_map(|(rows, (post_id, _))| rows.iter().map(move |row| (*post_id, row)))
+        .map(async |(post_id, (id, editor, date))| _revision(ajax, post_id, *id, editor, date, format).await)
+        .into_future_iter()
+        .buffered(max_threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut revisions = revisions.into_iter();
+    for (rows, (_, post_revisions)) in lists.iter().zip(edited.iter_mut()) {
+        **post_revisions = revisions.by_ref().take(rows.len()).flatten().collect();
+    }
+}
+
+fn _edited_posts(messages: &mut [Message]) -> Vec<(u64, &mut Box<[PostRevision]>)> {
+    messages.iter_mut()
+        .flat_map(|Message { id, edited, revisions, answers, .. }| {
+            id.filter(|_| *edited)
+                .map(|id| (id, revisions))
+                .into_iter()
+                .chain(_edited_posts(answers))
+        })
+        .collect()
+}
+
+/// ID, editor and date of the revisions of a post.
+async fn _revision_list(ajax: &WikidotAjax, post_id: u64) -> Box<[(u64, String, String)]> {
+    let post_id_str = post_id.to_string();
+    let list = match ajax.module_html("forum/sub/ForumPostRevisionsModule", &[("postId", post_id_str.as_str())]).await {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("[WARNING] Couldn't list the revisions of post {post_id}: {e}");
+            return Box::default();
+        }
+    };
+
+    list.select(&PR_SEL_TR)
+        .filter_map(|row| {
+            let id = row.select(&PR_SEL_SHOW).next()
+                .and_then(|link| link.attr("onclick"))
+                .and_then(|onclick| PR_REGEX_ID.captures(onclick))
+                .and_then(|captures| captures[1].parse::<u64>().ok())?;
+            let editor = row.select(&PR_SEL_EDITOR).last()
+                .map(|editor| editor.inner_html())
+                .unwrap_or("(account deleted)".to_string());
+            let date = row.select(&PR_SEL_DATE).next()
+                .map(|date| date.inner_html())
+                .unwrap_or("Unknown date".to_string());
+            Some((id, editor, date))
+        })
+        .collect()
+}
+
+/// None if the revision couldn't be downloaded: it's left out rather than saved empty.
+async fn _revision(ajax: &WikidotAjax, post_id: u64, id: u64, editor: &str, date: &str, format: MessageFormat) -> Option<PostRevision> {
+    let response = ajax.query("forum/sub/ForumPostRevisionModule", &[("revisionId", id.to_string().as_str())]).await
+        .inspect_err(|e| eprintln!("[WARNING] Couldn't download revision {id} of post {post_id}, skipping it: {e}"))
+        .ok()?;
+    let field = |name| response.get(name).and_then(Value::as_str).unwrap_or_default();
+    Some(PostRevision {
+        id,
+        editor: editor.to_string(),
+        date: date.to_string(),
+        title: field("title").trim().to_string(),
+        content: format.render(Html::parse_fragment(field("content")).root_element()),
+    })
+}
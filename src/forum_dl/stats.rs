@@ -0,0 +1,152 @@
+use crate::forum_dl::{Category, Message, Thread};
+use chrono::DateTime;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const MOST_ACTIVE_THREADS: usize = 20;
+
+#[derive(Serialize)]
+pub struct ForumStats {
+    total_posts: usize,
+    total_threads: usize,
+    posts_per_user: BTreeMap<String, usize>,
+    posts_per_month: BTreeMap<String, usize>,
+    most_active_threads: Box<[ThreadActivity]>,
+    reply_depth_distribution: BTreeMap<usize, usize>,
+    /// Median time between a post and the one it answers.
+    median_response_time_seconds: Option<i64>,
+    categories: Box<[CategoryActivity]>,
+}
+
+#[derive(Serialize)]
+struct ThreadActivity {
+    title: String,
+    url: String,
+    posts: usize,
+}
+
+#[derive(Serialize)]
+struct CategoryActivity {
+    name: String,
+    threads: usize,
+    posts: usize,
+    authors: usize,
+    first_post: Option<String>,
+    last_post: Option<String>,
+}
+
+/// A post, with its depth in the answers tree and the timestamp of the post it answers.
+struct PostInfo<'a> {
+    message: &'a Message,
+    depth: usize,
+    answered_timestamp: Option<i64>,
+}
+
+fn _posts(thread: &Thread) -> Vec<PostInfo<'_>> {
+    fn rec<'a>(messages: &'a [Message], depth: usize, parent: Option<i64>, posts: &mut Vec<PostInfo<'a>>) {
+        for message in messages {
+            posts.push(PostInfo { message, depth, answered_timestamp: parent });
+            rec(message.answers.as_ref(), depth + 1, message.timestamp, posts);
+        }
+    }
+
+    let mut posts = Vec::new();
+    /* Top-level posts answer the first post of the thread */
+    let first_timestamp = thread.messages.first().and_then(|message| message.timestamp);
+    for (i, message) in thread.messages.iter().enumerate() {
+        posts.push(PostInfo { message, depth: 0, answered_timestamp: if i == 0 { None } else { first_timestamp } });
+        rec(message.answers.as_ref(), 1, message.timestamp, &mut posts);
+    }
+    posts
+}
+
+fn _format_date(timestamp: i64, format: &str) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format(format).to_string())
+        .unwrap_or_default()
+}
+
+impl ForumStats {
+    pub fn compute(categories: &[Category]) -> Self {
+        let threads = categories.iter().flat_map(|category| category.threads.iter()).collect::<Box<[_]>>();
+        let posts = threads.iter().flat_map(|thread| _posts(thread)).collect::<Box<[_]>>();
+
+        let response_times = posts.iter()
+            .filter_map(|post| Some(post.message.timestamp? - post.answered_timestamp?))
+            .filter(|time| *time >= 0)
+            .sorted()
+            .collect::<Box<[_]>>();
+
+        Self {
+            total_posts: posts.len(),
+            total_threads: threads.len(),
+            posts_per_user: posts.iter().map(|post| post.message.author.clone()).counts().into_iter().collect(),
+            posts_per_month: posts.iter()
+                .filter_map(|post| post.message.timestamp)
+                .map(|timestamp| _format_date(timestamp, "%Y-%m"))
+                .counts().into_iter().collect(),
+            most_active_threads: threads.iter()
+                .map(|thread| ThreadActivity {
+                    title: thread.title.clone(),
+                    url: thread.url.clone(),
+                    posts: _posts(thread).len(),
+                })
+                .sorted_by_key(|thread| std::cmp::Reverse(thread.posts))
+                .take(MOST_ACTIVE_THREADS)
+                .collect(),
+            reply_depth_distribution: posts.iter().map(|post| post.depth).counts().into_iter().collect(),
+            median_response_time_seconds: response_times.get(response_times.len() / 2).copied(),
+            categories: categories.iter()
+                .map(|category| {
+                    let posts = category.threads.iter().flat_map(_posts).collect::<Box<[_]>>();
+                    let timestamps = posts.iter().filter_map(|post| post.message.timestamp);
+                    CategoryActivity {
+                        name: category.name.clone(),
+                        threads: category.threads.len(),
+                        posts: posts.len(),
+                        authors: posts.iter().map(|post| post.message.author.as_str()).unique().count(),
+                        first_post: timestamps.clone().min().map(|ts| _format_date(ts, "%Y-%m-%d")),
+                        last_post: timestamps.max().map(|ts| _format_date(ts, "%Y-%m-%d")),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Human-readable summary of the statistics.
+    pub fn summary(&self) -> String {
+        let table = |title: &str, rows: Vec<(String, String)>| {
+            let width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or_default().max(10);
+            let rows = rows.into_iter()
+                .map(|(name, value)| format!("  {name:<width$}  {value:>8}"))
+                .join("\n");
+            format!("{title}\n{rows}\n")
+        };
+
+        let median = self.median_response_time_seconds
+            .map(|seconds| format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60))
+            .unwrap_or("unknown".to_string());
+
+        [
+            format!("{} posts in {} threads. Median response time: {median}.\n", self.total_posts, self.total_threads),
+            table("Categories (threads, posts, authors):", self.categories.iter()
+                .map(|category| (category.name.clone(), format!("{} {} {}", category.threads, category.posts, category.authors)))
+                .collect()),
+            table("Most active users:", self.posts_per_user.iter()
+                .sorted_by_key(|(_, posts)| std::cmp::Reverse(**posts))
+                .take(20)
+                .map(|(user, posts)| (user.clone(), posts.to_string()))
+                .collect()),
+            table("Most active threads:", self.most_active_threads.iter()
+                .map(|thread| (thread.title.clone(), thread.posts.to_string()))
+                .collect()),
+            table("Posts per month:", self.posts_per_month.iter()
+                .map(|(month, posts)| (month.clone(), posts.to_string()))
+                .collect()),
+            table("Reply depth:", self.reply_depth_distribution.iter()
+                .map(|(depth, posts)| (depth.to_string(), posts.to_string()))
+                .collect()),
+        ].join("\n")
+    }
+}
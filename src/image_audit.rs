@@ -0,0 +1,196 @@
+use crate::attachments;
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::list_pages::{select_pages, PageSelection};
+use crate::wikidot_ajax::WikidotAjax;
+use crate::wikidot_source::{image_references, is_image, FileReference};
+use clap::Parser;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct ImageAuditParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Unix name of the page where the branch lists the licences of its images.
+    /// The lines of this page mentioning an audited page count as attributions for it.
+    #[arg(long, value_name = "PAGE")]
+    attribution_page: Option<String>,
+    /// Also outputs the pages where nothing is wrong.
+    #[arg(long, default_value = "false")]
+    all: bool,
+}
+
+lazy_static!(
+    static ref IA_REGEX_LICENSE_BOX: Regex = Regex::new(r"(?is)\[\[include\s+[^\]]*license-box\b[^\]]*\]\](.*?)(?:\[\[include\s+[^\]]*license-box-end[^\]]*\]\]|$)").unwrap();
+    static ref IA_REGEX_COLLAPSIBLE: Regex = Regex::new(r#"(?is)\[\[collapsible\s+[^\]]*show\s*=\s*"[^"]*(?:licen|attribution|citation|crédit|credit)[^"]*"[^\]]*\]\](.*?)\[\[/collapsible\]\]"#).unwrap();
+    static ref IA_REGEX_FILENAME: Regex = Regex::new(r"(?im)^[>\s]*\**\s*(?:file\s*name|nom (?:du|de) fichier)\s*:?\s*\**\s*:?\s*(.+?)\s*$").unwrap();
+    static ref IA_REGEX_WORD: Regex = Regex::new(r"[\w\-]+(?::[\w\-]+)*").unwrap();
+    static ref IA_REGEX_IMAGE_NAME: Regex = Regex::new(r"(?i)[\w\-.%()~]+\.(?:jpe?g|png|gif|webp|svg|bmp|tiff?|avif)\b").unwrap();
+);
+
+#[derive(Serialize)]
+struct PageAudit {
+    url: String,
+    title: String,
+    /// Images attached to the page or displayed by it that no licensing block mentions.
+    unattributed_images: Box<[String]>,
+    /// Files mentioned by a licensing block that the page neither has nor displays.
+    attributions_for_missing_files: Box<[String]>,
+    /// Images displayed from another website.
+    hotlinked_images: Box<[String]>,
+}
+
+impl PageAudit {
+    fn has_issues(&self) -> bool {
+        !self.unattributed_images.is_empty()
+            || !self.attributions_for_missing_files.is_empty()
+            || !self.hotlinked_images.is_empty()
+    }
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::ImageAudit(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+    let ajax = WikidotAjax::new(site.as_str(), script_data.verbose);
+
+    let pages = select_pages(&script_data, &params.selection, &["url", "wikidotInfo.title", "wikidotInfo.source"]).await;
+
+    let attribution_page = match params.attribution_page.as_ref() {
+        Some(page_name) => Some(_attribution_page_source(&ajax, &(site.clone() + page_name)).await),
+        None => None,
+    };
+
+    println!("Listing the files of {} page(s).", pages.len());
+    let urls = pages.iter().map(|page| (_get_str(page, "url").to_string(), None)).collect();
+    let files_lists = attachments::list_pages_files(&ajax, urls, true, script_data.threads).await;
+
+    let audits = pages.iter()
+        .zip(files_lists)
+        .map(|(page, files)| {
+            let attached = files.map(|files| files.iter()
+                .filter(|file| is_image(&file.name) || file.mime_type.as_deref().is_some_and(|mime| mime.starts_with("image/")))
+                .map(|file| file.name.clone())
+                .collect::<BTreeSet<_>>());
+            _audit_page(page, site.as_str(), attached, attribution_page.as_deref())
+        })
+        .collect::<Box<[_]>>();
+
+    let with_issues = audits.iter().filter(|audit| audit.has_issues()).count();
+    println!("{with_issues} page(s) out of {} have image attribution issues.", audits.len());
+
+    let audits = audits.into_iter()
+        .filter(|audit| params.all || audit.has_issues())
+        .collect::<Box<[_]>>();
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, audits.as_ref());
+    println!("Results written in file {}", path);
+}
+
+async fn _attribution_page_source(ajax: &WikidotAjax, url: &str) -> String {
+    let html = common_tools::download_webpage(url).await
+        .unwrap_or_else(|| panic!("Couldn't download the attribution page {url}."));
+    let page_id = attachments::page_id(&html)
+        .unwrap_or_else(|| panic!("No page ID found for the attribution page {url}."));
+    ajax.page_source(page_id).await
+        .unwrap_or_else(|e| panic!("Couldn't get the source of the attribution page {url}: {e}"))
+}
+
+fn _get_str<'a>(page: &'a Value, key: &str) -> &'a str {
+    page.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// `attached` is None when the files of the page couldn't be listed.
+fn _audit_page(page: &Value, site: &str, attached: Option<BTreeSet<String>>, attribution_page: Option<&str>) -> PageAudit {
+    let url = _get_str(page, "url");
+    let page_name = url.rsplit('/').next().unwrap_or_default();
+    let wikidot_info = page.get("wikidotInfo");
+    let source = wikidot_info.and_then(|info| info.get("source")).and_then(Value::as_str).unwrap_or_default();
+    let title = wikidot_info.and_then(|info| info.get("title")).and_then(Value::as_str).unwrap_or_default();
+
+    let references = image_references(source, site);
+    let hotlinked_images = references.iter()
+        .filter_map(|reference| match reference {
+            FileReference::External(url) => Some(url.clone()),
+            _ => None,
+        })
+        .collect();
+    /* Images of other pages are named after their page, as in their URL */
+    let displayed = references.iter()
+        .filter_map(|reference| match (reference.attached_to(page_name), reference) {
+            (Some(name), _) => Some(name.to_string()),
+            (None, FileReference::Attached { page: Some(page), name }) => Some(format!("{page}/{name}")),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    let attributed_in_page = _attributed_files(source);
+    let mut attributed = attributed_in_page.clone();
+    if let Some(attribution_page) = attribution_page {
+        attributed.extend(_attributions_in_page(attribution_page, page_name));
+    }
+    let is_attributed = |image: &str| attributed.contains(&_file_name(image).to_lowercase());
+
+    let files_known = attached.is_some();
+    if !files_known {
+        eprintln!("[WARNING] Files of {url} unknown, only the images of its source are audited.");
+    }
+    let attached = attached.unwrap_or_default();
+    let unattributed_images = attached.iter()
+        .chain(displayed.iter().filter(|image| !attached.contains(*image)))
+        .filter(|image| !is_attributed(image))
+        .cloned()
+        .collect();
+
+    let available = attached.iter().chain(displayed.iter())
+        .map(|image| _file_name(image).to_lowercase())
+        .collect::<BTreeSet<_>>();
+    let attributions_for_missing_files = attributed_in_page.into_iter()
+        .filter(|name| files_known && !available.contains(name))
+        .collect();
+
+    PageAudit {
+        url: url.to_string(),
+        title: title.to_string(),
+        unattributed_images,
+        attributions_for_missing_files,
+        hotlinked_images,
+    }
+}
+
+fn _file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Lowercase names of the files mentioned by the licensing blocks of a source
+/// (license boxes, licensing collapsibles, and "Filename:" lines).
+fn _attributed_files(source: &str) -> BTreeSet<String> {
+    let blocks = IA_REGEX_LICENSE_BOX.captures_iter(source)
+        .chain(IA_REGEX_COLLAPSIBLE.captures_iter(source))
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .flat_map(|block| IA_REGEX_IMAGE_NAME.find_iter(block).map(|name| name.as_str()));
+    let filename_lines = IA_REGEX_FILENAME.captures_iter(source)
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .map(|name| name.trim_matches(|c: char| c == '*' || c == '/' || c.is_whitespace()));
+
+    blocks.chain(filename_lines)
+        .map(|name| _file_name(name).to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Image names found on the lines of the attribution page mentioning the page.
+fn _attributions_in_page(attribution_page: &str, page_name: &str) -> BTreeSet<String> {
+    attribution_page.lines()
+        .filter(|line| IA_REGEX_WORD.find_iter(line).any(|word| word.as_str().eq_ignore_ascii_case(page_name)))
+        .flat_map(|line| IA_REGEX_IMAGE_NAME.find_iter(line))
+        .map(|name| name.as_str().to_lowercase())
+        .collect()
+}
@@ -0,0 +1,144 @@
+use crate::common_tools::FutureIterator;
+use futures_util::StreamExt;
+use image::imageops::FilterType;
+use itertools::Itertools;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the formats the image crate is built to decode.
+const DECODABLE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+/// An image of a download folder (`<folder>/<page>/<file>`) with its perceptual hash.
+#[derive(Serialize, Clone)]
+pub struct HashedImage {
+    pub folder: String,
+    pub page: String,
+    pub file: String,
+    /// 64-bit difference hash, in hexadecimal.
+    pub hash: String,
+    #[serde(skip)]
+    bits: u64,
+}
+
+/// Near-duplicate images used by more than one page.
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub pages: usize,
+    /// Whether the images come from several download folders (e.g. several branches).
+    pub across_folders: bool,
+    pub images: Box<[HashedImage]>,
+}
+
+/// Hashes the images of download folders, one subfolder per page.
+pub async fn hash_folders(folders: &[PathBuf], threads: usize) -> Vec<HashedImage> {
+    let images = folders.iter()
+        .flat_map(|folder| _list_images(folder))
+        .collect::<Box<[_]>>();
+    println!("Computing the perceptual hashes of {} image(s).", images.len());
+
+    images.into_iter()
+        .map(|(folder, page, file)| async move {
+            let path = folder.join(&page).join(&file);
+            let bits = tokio::task::spawn_blocking(move || _difference_hash(&path)).await.ok().flatten()?;
+            Some(HashedImage {
+                folder: folder.display().to_string(),
+                page,
+                file,
+                hash: format!("{bits:016x}"),
+                bits,
+            })
+        })
+        .into_future_iter()
+        .buffer_unordered(threads)
+        .filter_map(async |image| image)
+        .collect()
+        .await
+}
+
+fn _list_images(folder: &Path) -> Vec<(PathBuf, String, String)> {
+    let Ok(pages) = fs::read_dir(folder) else {
+        eprintln!("Could not read folder {}.", folder.display());
+        return vec![];
+    };
+    pages.filter_map(Result::ok)
+        .filter(|page| page.path().is_dir())
+        .flat_map(|page| {
+            let page_name = page.file_name().to_string_lossy().to_string();
+            fs::read_dir(page.path()).into_iter()
+                .flatten()
+                .filter_map(Result::ok)
+                .map(|file| file.file_name().to_string_lossy().to_string())
+                .filter(|file| _is_decodable(file))
+                .map(move |file| (folder.to_path_buf(), page_name.clone(), file))
+        })
+        .collect()
+}
+
+fn _is_decodable(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| DECODABLE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// dHash: compares the brightness of neighbouring pixels of a 9×8 grayscale thumbnail.
+/// Resizing, recompressing or slightly recolouring an image barely changes it.
+fn _difference_hash(path: &Path) -> Option<u64> {
+    let thumbnail = image::open(path)
+        .inspect_err(|e| eprintln!("[WARNING] Could not read image {}: {e}", path.display()))
+        .ok()?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+    let hash = (0..8)
+        .flat_map(|y| (0..8).map(move |x| (x, y)))
+        .fold(0u64, |hash, (x, y)| {
+            let brighter = thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0];
+            hash << 1 | brighter as u64
+        });
+    Some(hash)
+}
+
+/// Groups images whose hashes differ by at most `max_distance` bits, keeping the groups spanning several pages.
+pub fn duplicate_groups(images: &[HashedImage], max_distance: u32) -> Vec<DuplicateGroup> {
+    let mut parents = (0..images.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], i: usize) -> usize {
+        let mut i = i;
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    /* Hashes at most `max_distance` bits apart are identical on at least one of `max_distance + 1` bands of bits:
+    only the images sharing a band are compared, instead of every pair */
+    let bands = (max_distance as usize + 1).min(64);
+    let width = 64 / bands;
+    for band in 0..bands {
+        let start = band * width;
+        let bits = if band + 1 == bands { 64 - start } else { width };
+        let mask = if bits == 64 { u64::MAX } else { (1 << bits) - 1 };
+        let buckets = (0..images.len()).into_group_map_by(|i| images[*i].bits >> start & mask);
+        for bucket in buckets.values() {
+            for (&i, &j) in bucket.iter().tuple_combinations() {
+                if (images[i].bits ^ images[j].bits).count_ones() <= max_distance {
+                    let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+                    parents[root_i] = root_j;
+                }
+            }
+        }
+    }
+
+    let roots = (0..images.len()).map(|i| root(&mut parents, i)).collect::<Box<[_]>>();
+    (0..images.len())
+        .into_group_map_by(|i| roots[*i])
+        .into_values()
+        .map(|group| group.into_iter().map(|i| images[i].clone()).collect::<Box<[_]>>())
+        .map(|images| DuplicateGroup {
+            pages: images.iter().map(|image| (&image.folder, &image.page)).unique().count(),
+            across_folders: images.iter().map(|image| &image.folder).unique().count() > 1,
+            images,
+        })
+        .filter(|group| group.pages > 1)
+        .sorted_by_key(|group| std::cmp::Reverse(group.images.len()))
+        .collect()
+}
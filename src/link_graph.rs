@@ -0,0 +1,296 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::{xml_escape, FutureIterator};
+use crate::list_pages::{select_pages, PageSelection};
+use crate::wikidot_source::{page_links, page_name_from_url, LinkKind, PageLink};
+use clap::{Parser, ValueEnum};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::io::{BufWriter, Write};
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct LinkGraphParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Reads the links in the HTML of the pages instead of their sources: slower, but includes the links
+    /// added by included components. Includes aren't edges then.
+    #[arg(long, default_value = "false")]
+    from_html: bool,
+    /// Also writes the graph in the given file.
+    #[arg(long, value_name = "FILE")]
+    graph: Option<String>,
+    /// Format of the graph file.
+    #[arg(value_enum, long, default_value = "graphml", ignore_case = true, requires = "graph")]
+    graph_format: GraphFormat,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+pub enum GraphFormat {
+    /// One "from to kind" line per link, separated by tabs.
+    EdgeList,
+    #[value(name = "graphml")]
+    GraphML,
+    GEXF,
+}
+
+lazy_static!(
+    static ref LG_SEL_LINK: Selector = Selector::parse("#page-content a[href]").unwrap();
+);
+
+/// Links between the selected pages, and the broken links to pages that don't exist.
+#[derive(Serialize)]
+struct LinkGraph {
+    pages: Vec<Node>,
+    /// Only written in the graph file.
+    #[serde(skip)]
+    edges: Vec<Edge>,
+    /// Selected pages no other selected page links to.
+    orphans: Vec<String>,
+    broken_links: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    name: String,
+    url: String,
+    title: String,
+    hub: bool,
+    in_degree: usize,
+    out_degree: usize,
+    /// Number of hubs (pages tagged "hub") linking to the page.
+    linked_from_hubs: usize,
+}
+
+#[derive(Serialize)]
+struct Edge {
+    from: String,
+    to: String,
+    kind: LinkKind,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::LinkGraph(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+
+    let info: &[&str] = if params.from_html {
+        &["url", "wikidotInfo.title", "wikidotInfo.tags"]
+    } else {
+        &["url", "wikidotInfo.title", "wikidotInfo.tags", "wikidotInfo.source"]
+    };
+    let pages = select_pages(&script_data, &params.selection, info).await;
+
+    /* A link to a page outside of the selection is only broken if the page doesn't exist at all */
+    let selects_all = params.selection.all_tags.is_empty()
+        && params.selection.one_of_tags.is_empty()
+        && params.selection.author.is_none();
+    let existing = if selects_all {
+        pages.iter().map(_page_name).collect::<BTreeSet<_>>()
+    } else {
+        println!("Listing all the pages of the site to find the broken links.");
+        select_pages(&script_data, &PageSelection::default(), &["url"]).await
+            .iter().map(_page_name).collect()
+    };
+
+    let links = pages.iter()
+        .map(|page| _links(page, site.as_str(), params.from_html))
+        .into_future_iter()
+        .buffered(script_data.threads)
+        .collect::<Vec<_>>()
+        .await;
+
+    let graph = _build_graph(&pages, links, &existing);
+    println!(
+        "{} page(s), {} link(s) between them, {} orphan(s), {} broken link(s).",
+        graph.pages.len(),
+        graph.pages.iter().map(|node| node.out_degree).sum::<usize>(),
+        graph.orphans.len(),
+        graph.broken_links.len(),
+    );
+    if params.from_html {
+        println!("Links read from the HTML of the pages: includes aren't counted, the links they add are.");
+    }
+
+    if let Some(path) = params.graph.as_ref() {
+        let file = fs::File::create(path).unwrap_or_else(|e| panic!("--graph: could not create file {path}: {e}"));
+        _write_graph(BufWriter::new(file), params.graph_format, &graph)
+            .unwrap_or_else(|e| panic!("--graph: could not write file {path}: {e}"));
+        println!("Graph written in file {path}");
+    }
+
+    let path = script_data.output.path().clone();
+    common_tools::write_serialized(script_data.output, &script_data.output_format, &graph);
+    println!("Results written in file {}", path);
+}
+
+fn _page_name(page: &Value) -> String {
+    page.get("url").and_then(Value::as_str).and_then(|url| page_name_from_url(url, Url::parse(url).ok()?.host_str()))
+        .unwrap_or_default()
+}
+
+fn _str<'a>(page: &'a Value, pointer: &str) -> &'a str {
+    page.pointer(pointer).and_then(Value::as_str).unwrap_or_default()
+}
+
+async fn _links(page: &Value, site: &str, from_html: bool) -> BTreeSet<PageLink> {
+    if !from_html {
+        return page_links(_str(page, "/wikidotInfo/source"), site);
+    }
+
+    let url = _str(page, "/url");
+    let Some(html) = common_tools::download_webpage(url).await else {
+        eprintln!("[WARNING] Couldn't download {url}, its links are missing.");
+        return BTreeSet::new();
+    };
+    let site_host = Url::parse(site).ok().and_then(|site| site.host_str().map(String::from));
+    Html::parse_document(&html).select(&LG_SEL_LINK)
+        .filter_map(|link| link.attr("href"))
+        .filter_map(|href| page_name_from_url(href, site_host.as_deref()))
+        .map(|target| PageLink { target, kind: LinkKind::Link })
+        .collect()
+}
+
+fn _build_graph(pages: &[Value], links: Vec<BTreeSet<PageLink>>, existing: &BTreeSet<String>) -> LinkGraph {
+    let names = pages.iter().map(_page_name).collect::<Vec<_>>();
+    let hubs = pages.iter().zip(&names)
+        .filter(|(page, _)| page.pointer("/wikidotInfo/tags").and_then(Value::as_array)
+            .is_some_and(|tags| tags.iter().any(|tag| tag.as_str() == Some("hub"))))
+        .map(|(_, name)| name.as_str())
+        .collect::<BTreeSet<_>>();
+    let selected = names.iter().map(String::as_str).collect::<BTreeSet<_>>();
+
+    let mut edges = vec![];
+    let mut broken_links = vec![];
+    for (from, links) in names.iter().zip(links) {
+        /* A page both linked and included counts as one edge, of the first kind */
+        let mut targets = BTreeSet::new();
+        for link in links.into_iter().filter(|link| &link.target != from) {
+            if !existing.contains(&link.target) {
+                broken_links.push(Edge { from: from.clone(), to: link.target, kind: link.kind });
+            } else if selected.contains(link.target.as_str()) && targets.insert(link.target.clone()) {
+                edges.push(Edge { from: from.clone(), to: link.target, kind: link.kind });
+            }
+        }
+    }
+
+    let mut in_degrees = BTreeMap::<&str, usize>::new();
+    let mut out_degrees = BTreeMap::<&str, usize>::new();
+    let mut from_hubs = BTreeMap::<&str, usize>::new();
+    for edge in &edges {
+        *in_degrees.entry(edge.to.as_str()).or_default() += 1;
+        *out_degrees.entry(edge.from.as_str()).or_default() += 1;
+        if hubs.contains(edge.from.as_str()) {
+            *from_hubs.entry(edge.to.as_str()).or_default() += 1;
+        }
+    }
+
+    let nodes = pages.iter().zip(&names)
+        .map(|(page, name)| Node {
+            name: name.clone(),
+            url: _str(page, "/url").to_string(),
+            title: _str(page, "/wikidotInfo/title").to_string(),
+            hub: hubs.contains(name.as_str()),
+            in_degree: in_degrees.get(name.as_str()).copied().unwrap_or_default(),
+            out_degree: out_degrees.get(name.as_str()).copied().unwrap_or_default(),
+            linked_from_hubs: from_hubs.get(name.as_str()).copied().unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+
+    LinkGraph {
+        orphans: nodes.iter().filter(|node| node.in_degree == 0).map(|node| node.url.clone()).collect(),
+        pages: nodes,
+        edges,
+        broken_links,
+    }
+}
+
+fn _write_graph(mut writer: impl Write, format: GraphFormat, graph: &LinkGraph) -> io::Result<()> {
+    match format {
+        GraphFormat::EdgeList => {
+            writeln!(writer, "from\tto\tkind")?;
+            for edge in &graph.edges {
+                writeln!(writer, "{}\t{}\t{}", edge.from, edge.to, _kind_name(edge.kind))?;
+            }
+        }
+        GraphFormat::GraphML => {
+            writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+            for (key, target, kind) in [("url", "node", "string"), ("title", "node", "string"), ("hub", "node", "boolean"),
+                ("in_degree", "node", "int"), ("out_degree", "node", "int"), ("kind", "edge", "string")] {
+                writeln!(writer, r#"  <key id="{key}" for="{target}" attr.name="{key}" attr.type="{kind}"/>"#)?;
+            }
+            writeln!(writer, r#"  <graph id="links" edgedefault="directed">"#)?;
+            for node in &graph.pages {
+                writeln!(writer, r#"    <node id="{}">"#, xml_escape(&node.name))?;
+                writeln!(writer, r#"      <data key="url">{}</data>"#, xml_escape(&node.url))?;
+                writeln!(writer, r#"      <data key="title">{}</data>"#, xml_escape(&node.title))?;
+                writeln!(writer, r#"      <data key="hub">{}</data>"#, node.hub)?;
+                writeln!(writer, r#"      <data key="in_degree">{}</data>"#, node.in_degree)?;
+                writeln!(writer, r#"      <data key="out_degree">{}</data>"#, node.out_degree)?;
+                writeln!(writer, "    </node>")?;
+            }
+            for edge in &graph.edges {
+                writeln!(
+                    writer,
+                    r#"    <edge source="{}" target="{}"><data key="kind">{}</data></edge>"#,
+                    xml_escape(&edge.from), xml_escape(&edge.to), _kind_name(edge.kind),
+                )?;
+            }
+            writeln!(writer, "  </graph>")?;
+            writeln!(writer, "</graphml>")?;
+        }
+        GraphFormat::GEXF => {
+            writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(writer, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#)?;
+            writeln!(writer, r#"  <graph defaultedgetype="directed">"#)?;
+            writeln!(writer, r#"    <attributes class="node">"#)?;
+            for (id, (title, kind)) in [("url", "string"), ("hub", "boolean"), ("in_degree", "integer"), ("out_degree", "integer")].iter().enumerate() {
+                writeln!(writer, r#"      <attribute id="{id}" title="{title}" type="{kind}"/>"#)?;
+            }
+            writeln!(writer, "    </attributes>")?;
+            writeln!(writer, r#"    <attributes class="edge">"#)?;
+            writeln!(writer, r#"      <attribute id="0" title="kind" type="string"/>"#)?;
+            writeln!(writer, "    </attributes>")?;
+            writeln!(writer, "    <nodes>")?;
+            for node in &graph.pages {
+                writeln!(writer, r#"      <node id="{}" label="{}">"#, xml_escape(&node.name), xml_escape(&node.title))?;
+                writeln!(writer, "        <attvalues>")?;
+                for (id, value) in [xml_escape(&node.url), node.hub.to_string(), node.in_degree.to_string(), node.out_degree.to_string()].iter().enumerate() {
+                    writeln!(writer, r#"          <attvalue for="{id}" value="{value}"/>"#)?;
+                }
+                writeln!(writer, "        </attvalues>")?;
+                writeln!(writer, "      </node>")?;
+            }
+            writeln!(writer, "    </nodes>")?;
+            writeln!(writer, "    <edges>")?;
+            for (id, edge) in graph.edges.iter().enumerate() {
+                writeln!(
+                    writer,
+                    r#"      <edge id="{id}" source="{}" target="{}"><attvalues><attvalue for="0" value="{}"/></attvalues></edge>"#,
+                    xml_escape(&edge.from), xml_escape(&edge.to), _kind_name(edge.kind),
+                )?;
+            }
+            writeln!(writer, "    </edges>")?;
+            writeln!(writer, "  </graph>")?;
+            writeln!(writer, "</gexf>")?;
+        }
+    }
+    writer.flush()
+}
+
+fn _kind_name(kind: LinkKind) -> &'static str {
+    match kind {
+        LinkKind::Link => "link",
+        LinkKind::Include => "include",
+    }
+}
@@ -1,21 +1,61 @@
+use crate::attachments;
+use crate::attachments::AttachmentDownloader;
 use crate::cli::{Cli, Script};
 use crate::common_tools;
-use crate::common_tools::FutureIterator;
+use crate::common_tools::{File, FutureIterator};
+use crate::image_hash;
+use crate::list_pages;
+use crate::list_pages::PageSelection;
+use crate::session;
+use crate::wikidot_ajax::WikidotAjax;
+use crate::wikidot_source::file_references;
 use clap::Parser;
-use futures_util::{FutureExt, StreamExt};
+use futures_util::StreamExt;
 use reqwest::Client;
-use scraper::{ElementRef, Html, Selector};
+use scraper::{ElementRef, Selector};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use lazy_static::lazy_static;
 
 #[derive(Parser)]
 #[command(version = "0.1.0")]
 pub struct ListFilesParameters {
-    /// Unix name of the page where the ListPages module listing the pages whose files you want to list is located., value_name = "URL"
-    listpages_location: String,
+    /// Unix name of the page where the ListPages module listing the pages whose files you want to list is located.
+    /// Without it, pages are selected with Crom like with list-pages, or read from --pages-file.
+    #[arg(conflicts_with_all = ["pages_file", "all_tags", "one_of_tags", "author"])]
+    listpages_location: Option<String>,
+    /// File listing the pages whose files you want to list, one URL or unix name per line.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all_tags", "one_of_tags", "author"])]
+    pages_file: Option<String>,
+    #[command(flatten)]
+    selection: PageSelection,
+    /// [REQUIRES CHROMIUM] Lists the files by clicking the Files button of each page in a browser,
+    /// instead of asking Wikidot's files module directly. The browser is otherwise only used for the pages where the latter fails.
+    #[arg(long, default_value = "false")]
+    files_browser: bool,
     /// Shows the browser
     #[arg(long, default_value = "false")]
     no_headless: bool,
+    /// Downloads the files in the given folder, one subfolder per page.
+    #[arg(long, value_name = "FOLDER", default_value = None)]
+    download_files: Option<String>,
+    /// Reports the files never used in the source of their page or of its fragments,
+    /// and the files the source uses that the page doesn't have. Gets the sources from Crom.
+    #[arg(long, default_value = "false")]
+    unused_files: bool,
+    /// Writes in the given file the groups of near-duplicate images used by several pages,
+    /// found with perceptual hashes of the downloaded images.
+    #[arg(long, value_name = "FILE", requires = "download_files")]
+    duplicate_images: Option<String>,
+    /// Folders of previous --download-files runs (e.g. on other branches) whose images are also compared.
+    #[arg(long, value_name = "FOLDER", num_args = 1.., requires = "duplicate_images")]
+    compare_with: Vec<String>,
+    /// Maximum number of differing bits (out of 64) between the hashes of two near-duplicate images.
+    #[arg(long, default_value = "6", requires = "duplicate_images")]
+    hash_distance: u32,
 }
 
 lazy_static!(
@@ -38,15 +78,30 @@ async fn _get_file_list_from_listpage_page(client: Arc<Client>, url: String) ->
         .collect()
 }
 
-pub async fn list_files(mut script_data: Cli) {
-    let Script::ListFiles(params) = &mut script_data.script else {
-        panic!("Unreachable code")
+/// Files of the page never referenced in its source, and files referenced as attached to the page that it doesn't have.
+fn _files_usage(site: &str, page_name: &str, files: &[File], source: Option<&str>) -> (Box<[String]>, Box<[String]>) {
+    let Some(source) = source else {
+        eprintln!("[WARNING] Source of {page_name} not found in Crom, can't tell which files are used.");
+        return (Box::new([]), Box::new([]));
     };
+    let used = file_references(source, site).iter()
+        .filter_map(|reference| reference.attached_to(page_name).map(String::from))
+        .collect::<BTreeSet<_>>();
+    let unused = files.iter()
+        .filter(|file| !used.contains(&file.name))
+        .map(|file| file.name.clone())
+        .collect();
+    let missing = used.iter()
+        .filter(|name| !files.iter().any(|file| &file.name == *name))
+        .cloned()
+        .collect();
+    (unused, missing)
+}
 
-    let client = Client::new();
-    let site_url = script_data.site.clone().unwrap();
-    let listpages_url = site_url.clone() + params.listpages_location.as_str();
-    let first_page = common_tools::download_html(&client, listpages_url.as_str(), 5).await
+/// Pages listed by a ListPages module of the wiki, through all the pages of its pager.
+async fn _pages_from_listpages(client: &Client, site_url: &str, location: &str, threads: usize) -> Box<[String]> {
+    let listpages_url = site_url.to_string() + location;
+    let first_page = common_tools::download_html(client, listpages_url.as_str(), 5).await
         .expect("Failed to download the page containing the ListPages module.");
 
     let page_count = first_page.select(&PAGE_SELECTOR).next()
@@ -57,54 +112,137 @@ pub async fn list_files(mut script_data: Cli) {
         .expect("Pager page indicator is empty.")
         .parse::<usize>().expect("Could not parse the number of pages from the pager.");
 
-    let arc_client = Arc::new(client);
+    let arc_client = Arc::new(client.clone());
 
-    let page_list = (1..=page_count).map(|page_nb| {
+    (1..=page_count).map(|page_nb| {
         listpages_url.clone() + "/p/" + page_nb.to_string().as_str()
     })
         .map(|page_url| _get_file_list_from_listpage_page(arc_client.clone(), page_url))
         .into_future_iter()
-        .buffer_unordered(script_data.threads)
+        .buffer_unordered(threads)
         .collect::<Vec<_>>()
         .await
         .into_iter()
         .flatten()
-        .collect::<Box<[_]>>();
+        .collect()
+}
+
+/// Pages of a file, one URL or unix name per line. Empty lines and lines starting with # are ignored.
+fn _pages_from_file(path: &str) -> Box<[String]> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read file {path}: {e}"))
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(_unix_name)
+        .collect()
+}
+
+fn _unix_name(url: &str) -> String {
+    url.trim_end_matches('/').rsplit('/').next().unwrap_or(url).to_string()
+}
+
+pub async fn list_files(script_data: Cli) {
+    let Script::ListFiles(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+
+    let client = session::http_client();
+    let site_url = script_data.site.clone().unwrap();
+    let downloader = params.download_files.as_ref()
+        .map(|folder| AttachmentDownloader::new(site_url.as_str(), Path::new(folder)));
+    let page_list = match (params.listpages_location.as_ref(), params.pages_file.as_ref()) {
+        (Some(location), _) => _pages_from_listpages(&client, site_url.as_str(), location, script_data.threads).await,
+        (None, Some(pages_file)) => _pages_from_file(pages_file),
+        (None, None) => {
+            if params.selection.is_empty() {
+                eprintln!("[WARNING] No ListPages location, pages file, tags or author given: listing the files of every page of the site.");
+            }
+            list_pages::select_pages(&script_data, &params.selection, &["url"]).await
+                .iter()
+                .filter_map(|page| page.get("url").and_then(Value::as_str))
+                .map(_unix_name)
+                .collect()
+        }
+    };
 
     println!("{} pages found.", page_list.len());
 
-    let (browser, handler) = common_tools::open_browser(!params.no_headless).await;
-    let arc_browser = Arc::new(browser);
-
-    let pages_html = page_list.into_iter()
-        .map(|url| async {
-            println!("Downloading {url}");
-            let page = common_tools::download_webpage_browser(
-                (site_url.clone() + url.as_str()).as_str(),
-                arc_browser.clone().as_ref()
-            ).await.map(Box::new); /* Boxed because too big for the stack */
-            (url, page)
-        }.boxed()) /* Boxed because too big for the stack */
-        .into_future_iter()
-        .buffer_unordered(script_data.threads)
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .filter_map(|(url, page_content)| page_content.map(|p| (url, p)))
-        .map(|(url, page_content)| (url, Html::parse_document(page_content.as_str())))
-        .map(|(url, html)| (url, common_tools::file_list(&html)))
+    let page_urls = page_list.iter().map(|url| site_url.clone() + url.as_str()).collect::<Box<[_]>>();
+    let ajax = WikidotAjax::new(site_url.as_str(), script_data.verbose);
+    let files_lists = if params.files_browser {
+        attachments::list_files_browser(page_urls.as_ref(), !params.no_headless, script_data.threads).await
+            .into_iter()
+            .map(|(_, files)| files)
+            .collect()
+    } else {
+        let pages = page_urls.iter().map(|url| (url.clone(), None)).collect();
+        attachments::list_pages_files(&ajax, pages, !params.no_headless, script_data.threads).await
+    };
+
+    let mut pages_files = page_list.into_iter()
+        .zip(files_lists)
+        .filter_map(|(url, files)| files.map(|files| (url, files)))
         .filter(|(_, files)| !files.is_empty())
-        .map(|(url, files)| {
-            [
+        .collect::<Box<[_]>>();
+
+    let details_pages = pages_files.iter_mut().map(|(url, files)| (url.as_str(), files.as_mut()));
+    attachments::fetch_files_details(&ajax, site_url.as_str(), details_pages, script_data.threads).await;
+
+    if let Some(downloader) = downloader.as_ref() {
+        let entries = pages_files.iter_mut()
+            .map(|(url, files)| downloader.download_page_files(url.as_str(), files))
+            .into_future_iter()
+            .buffer_unordered(script_data.threads)
+            .collect::<Vec<_>>()
+            .await;
+        downloader.save_manifest(entries.into_iter().flatten());
+    }
+
+    if let Some(duplicates_path) = params.duplicate_images.as_ref() {
+        let folders = params.download_files.iter().chain(params.compare_with.iter())
+            .map(PathBuf::from)
+            .collect::<Box<[_]>>();
+        let images = image_hash::hash_folders(folders.as_ref(), script_data.threads).await;
+        let groups = image_hash::duplicate_groups(images.as_ref(), params.hash_distance);
+        println!(
+            "{} group(s) of images reused by several pages, {} of them across folders.",
+            groups.len(),
+            groups.iter().filter(|group| group.across_folders).count(),
+        );
+        let file = fs::File::create(duplicates_path)
+            .unwrap_or_else(|e| panic!("Could not create file {duplicates_path}: {e}"));
+        common_tools::write_serialized(file, &script_data.output_format, &groups);
+    }
+
+    let usages = if params.unused_files {
+        let urls = pages_files.iter().map(|(url, _)| site_url.clone() + url.as_str()).collect::<Box<[_]>>();
+        let sources = list_pages::pages_sources(&script_data, urls.as_ref()).await;
+        pages_files.iter()
+            .zip(sources)
+            .map(|((url, files), source)| _files_usage(site_url.as_str(), url, files, source.as_deref()))
+            .map(Some)
+            .collect()
+    } else {
+        vec![None; pages_files.len()]
+    };
+
+    let pages_html = pages_files.into_iter()
+        .zip(usages)
+        .map(|((url, files), usage)| {
+            let mut entries = vec![
                 ("url", serde_json::to_value(url).unwrap()),
-                ("total size", serde_json::to_value(files.iter().map(|file| file.size).sum::<i32>()).unwrap()),
+                ("total size", serde_json::to_value(files.iter().map(|file| file.size).sum::<u64>()).unwrap()),
                 ("files", serde_json::to_value(files).unwrap())
-            ]
+            ];
+            if let Some((unused, missing)) = usage {
+                entries.push(("unused files", serde_json::to_value(unused).unwrap()));
+                entries.push(("missing files", serde_json::to_value(missing).unwrap()));
+            }
+            entries
         })
         .collect::<Box<[_]>>();
 
-    common_tools::close_browser((Arc::into_inner(arc_browser).unwrap(), handler)).await;
-
     let path = script_data.output.path().clone();
 
     common_tools::write_out(script_data, pages_html.as_ref());
@@ -1,12 +1,9 @@
-use clap::Parser;
+use crate::anonymize::AnonymizeParameters;
+use clap::{Args, Parser};
 
-#[derive(Parser)]
-#[command(version = "0.3.0")]
-#[derive(Debug)]
-pub struct ListPagesParameters {
-    /// Defines the information requested from Crom, separated by spaces or commas.
-    #[arg(long, short, default_value = "url wikidotInfo.title", num_args = 1..)]
-    pub info: Vec<String>,
+/// Criteria selecting pages with Crom, shared by the scripts working on a set of pages.
+#[derive(Args, Debug, Default)]
+pub struct PageSelection {
     /// Pages must include all following tags.
     #[arg(long, short = 'T', value_name = "TAG", num_args = 1..)]
     pub all_tags: Vec<String>,
@@ -16,6 +13,54 @@ pub struct ListPagesParameters {
     /// Searches within the pages attributed to the given author.
     #[arg(long, short)]
     pub author: Option<String>,
+}
+
+impl PageSelection {
+    #[cfg(feature = "list-files")]
+    /// Whether no criterion was given, which selects every page of the site.
+    pub fn is_empty(&self) -> bool {
+        self.all_tags.is_empty() && self.one_of_tags.is_empty() && self.author.is_none()
+    }
+
+    /// Crom filter on wikidotInfo built from the tags criteria.
+    pub fn crom_filter(&self) -> Option<String> {
+        let crom_recursive_query_builder = |operation|
+            move |acc: String, tag| {
+                let tag_filter = format!("{{ tags: {{ eq: \"{tag}\" }} }}");
+                if acc.is_empty() {
+                    tag_filter
+                } else {
+                    format!("{{ _{operation}: [{tag_filter}, {acc}] }}")
+                }
+            };
+
+        let filter_and = self
+            .all_tags
+            .iter()
+            .fold(String::new(), crom_recursive_query_builder("and"));
+
+        let filter_or = self
+            .one_of_tags
+            .iter()
+            .fold(String::new(), crom_recursive_query_builder("or"));
+
+        match (filter_or.as_str(), filter_and.as_str()) {
+            ("", "") => None,
+            ("", yes) | (yes, "") => Some(yes.to_string()),
+            (or, and) => Some(format!("{{ _and: [ {and}, {or} ] }}")),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(version = "0.3.0")]
+#[derive(Debug)]
+pub struct ListPagesParameters {
+    /// Defines the information requested from Crom, separated by spaces or commas.
+    #[arg(long, short, default_value = "url wikidotInfo.title", num_args = 1..)]
+    pub info: Vec<String>,
+    #[command(flatten)]
+    pub selection: PageSelection,
     /// Downloads the contents of each page from the HTML page.
     #[arg(long, default_value = "false")]
     pub content: bool,
@@ -37,15 +82,34 @@ pub struct ListPagesParameters {
     /// Sets default parameters to scrap the website for analysis with TXM. Overrides --content, --gather-fragment-sources, --format. Disables --source-contains.
     #[arg(long, default_value = "false")]
     pub txm: bool,
-    /// [REQUIRES CHROMIUM] Lists the files of listed pages
+    /// Lists the files of listed pages. Uses Chromium for the pages where Wikidot's files module can't be used.
     #[arg(long, short, default_value = "false")]
     pub files: bool,
+    /// [REQUIRES CHROMIUM] Lists the files by clicking the Files button of each page in a browser. Implies --files.
+    #[arg(long, default_value = "false")]
+    pub files_browser: bool,
+    /// Downloads the files of listed pages in the given folder, one subfolder per page. Implies --files.
+    #[arg(long, value_name = "FOLDER", default_value = None)]
+    pub download_files: Option<String>,
+    /// Downloads who voted for each page and how, from Crom if it has the data, otherwise from Wikidot.
+    #[arg(long, default_value = "false")]
+    pub votes: bool,
+    /// Extracts from the source of each page its CSS modules, theme includes, modules and div classes.
+    /// Adds wikidotInfo.source to --info if not specified.
+    #[arg(long, default_value = "false")]
+    pub styles: bool,
+    #[command(flatten)]
+    pub anonymize: AnonymizeParameters,
 }
 
 impl ListPagesParameters {
 
     /// Applies automatic inferences linking some params to others
     pub fn apply_inferences(&mut self) {
+        if self.download_files.is_some() || self.files_browser {
+            self.files = true;
+        }
+
         if self.txm {
             const TXM_PARAMS: [&str; 7] = [
                 "url",
@@ -60,12 +124,12 @@ impl ListPagesParameters {
             self.content = true;
         } else {
             let url_str = "url".to_string();
-            if self.content && !self.info.contains(&url_str) {
+            if (self.content || self.votes) && !self.info.contains(&url_str) {
                 self.info.push(url_str);
             }
 
             let source_str = "wikidotInfo.source".to_string();
-            if (!self.source_contains.is_empty() || self.gather_fragments_sources)
+            if (!self.source_contains.is_empty() || self.gather_fragments_sources || self.styles)
                 && !self.info.contains(&source_str)
             {
                 self.info.push(source_str);
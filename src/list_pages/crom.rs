@@ -42,6 +42,26 @@ impl Crom {
             .expect("Too many failed attempts: giving up.")
     }
 
+    /// Single attempt, for queries whose errors won't go away by retrying (e.g. fields Crom may not have).
+    pub async fn try_query(&self, request: &str) -> Result<Value, Box<dyn Error>> {
+        self._wait_for_ratelimit().await;
+        let res: Value = self.client
+            .post(CROM_URL)
+            .header(USER_AGENT, "ScpScriptAnthology/1.0")
+            .json(&serde_json::json!({"query": request}))
+            .send().await?
+            .json().await?;
+        if self.verbose {
+            println!("Query: {request}");
+            println!("Response: {res}");
+        }
+
+        match res.get("errors") {
+            Some(errors) => Err(CromError { errors: errors.to_string() }.into()),
+            None => Ok(res),
+        }
+    }
+
     pub async fn _wait_for_ratelimit(&self) {
         const RATE_LIMIT_REQUEST: &str = "query {rateLimit{remaining, resetAt}}";
 
@@ -126,6 +146,34 @@ impl Crom {
         }
     }
 
+    #[cfg(feature = "list-files")]
+    /// Source of a page followed by the sources of its fragments. None if Crom doesn't know the page.
+    pub async fn page_sources(&self, url: &str) -> Option<String> {
+        let query = format!("query {{ page(url: \"{url}\") {{ wikidotInfo {{ source, children {{ url }} }} }} }}");
+        if self.verbose {
+            println!("Query: {query}");
+        }
+        let response = self.query(query.as_str()).await;
+        if self.verbose {
+            println!("Response: {response}");
+        }
+        let wikidot_info = response.get("data")
+            .and_then(|data| data.get("page"))
+            .and_then(|page| page.get("wikidotInfo"))?;
+
+        let fragments = wikidot_info.get("children")
+            .and_then(Value::as_array)
+            .map(|children| children.iter()
+                .filter(|child| child.get("url").and_then(Value::as_str).is_some_and(|url| url.contains("fragment:")))
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+        let mut sources = vec![wikidot_info.get("source").and_then(Value::as_str).unwrap_or_default().to_string()];
+        for fragment in fragments {
+            sources.push(self._get_fragment_source(fragment).await);
+        }
+        Some(sources.join("\n"))
+    }
+
     pub async fn _get_fragment_source(&self, fragment: &Value) -> String {
         let query = &format!(
             "
@@ -1,13 +1,22 @@
 mod cli;
 mod crom;
+mod votes;
 
+use crate::anonymize::Anonymizer;
+use crate::attachments;
+use crate::attachments::AttachmentDownloader;
 use crate::cli::{Cli, Script};
 use crate::common_tools;
-use crate::common_tools::{close_browser, download_webpage_browser, file_list, open_browser, xml_escape, FutureIterator};
-use crate::list_pages::crom::{Crom, QueryTree};
+use crate::common_tools::{close_browser, download_webpage_browser, file_list, open_browser, xml_escape, File, FutureIterator};
+use crate::list_pages::crom::QueryTree;
+use crate::wikidot_ajax::WikidotAjax;
+use crate::wikidot_source::style_inventory;
 use chromiumoxide::Browser;
 use chrono::DateTime;
 pub(crate) use cli::ListPagesParameters;
+#[cfg(any(feature = "list-files", feature = "image-audit", feature = "page-history", feature = "backup", feature = "link-graph", feature = "check-links", feature = "components", feature = "styles-summary"))]
+pub(crate) use cli::PageSelection;
+pub(crate) use crom::Crom;
 use futures_util::{stream, StreamExt};
 use regex::{Regex, RegexBuilder};
 use scraper::Html;
@@ -36,7 +45,11 @@ pub async fn run(mut script_data: Cli) {
     let formatted_info = QueryTree::from_vec(params.info.iter().map(|s| s.as_str()).collect())
         .into_iter().map(|qt| qt.to_string()).collect::<Box<[_]>>().concat();
 
-    let result: Box<[Value]> = ListPages::new(&script_data, params, html_folder, formatted_info).execute().await;
+    let mut result: Box<[Value]> = ListPages::new(&script_data, params, html_folder, formatted_info).execute().await;
+
+    if let Some(anonymizer) = params.anonymize.anonymizer() {
+        anonymizer.anonymize(result.as_mut());
+    }
 
     println!("{} result(s) found.", result.len());
 
@@ -52,6 +65,57 @@ pub async fn run(mut script_data: Cli) {
     println!("Results written in file {}", path);
 }
 
+#[cfg(any(feature = "list-files", feature = "image-audit", feature = "page-history", feature = "backup", feature = "link-graph", feature = "check-links", feature = "components", feature = "styles-summary"))]
+/// Lists the pages matching a selection with Crom, for the scripts working on a set of pages.
+/// Sources are gathered from the fragments when `wikidotInfo.source` is requested.
+pub async fn select_pages(global_data: &Cli, selection: &PageSelection, info: &[&str]) -> Box<[Value]> {
+    let gather_fragments_sources = info.contains(&"wikidotInfo.source");
+    let mut info = info.to_vec();
+    if !info.contains(&"url") {
+        info.push("url");
+    }
+    if gather_fragments_sources && !info.contains(&"wikidotInfo.children.url") {
+        info.push("wikidotInfo.children.url");
+    }
+    let requested_data = QueryTree::from_vec(info)
+        .into_iter().map(|qt| qt.to_string()).collect::<Box<[_]>>().concat();
+    let site = global_data.site.as_ref().unwrap();
+
+    ListPages {
+        verbose: global_data.verbose,
+        site,
+        filter: selection.crom_filter(),
+        author: selection.author.as_deref(),
+        requested_data,
+        gather_fragments_sources,
+        download_content: false,
+        download_html: None,
+        get_files: false,
+        files_browser: false,
+        get_votes: false,
+        get_styles: false,
+        download_files: None,
+        source_contains_one: false,
+        threads: global_data.threads,
+        regexes_in_source: Box::new([]),
+        crom: Crom::new(global_data.verbose),
+        ajax: WikidotAjax::new(site, global_data.verbose),
+        anonymizer: None,
+    }.execute().await
+}
+
+#[cfg(feature = "list-files")]
+/// Sources of pages (with their fragments) from Crom, in the same order as the URLs.
+pub async fn pages_sources(global_data: &Cli, urls: &[String]) -> Vec<Option<String>> {
+    let crom = Crom::new(global_data.verbose);
+    urls.iter()
+        .map(|url| crom.page_sources(url))
+        .into_future_iter()
+        .buffered(global_data.threads)
+        .collect()
+        .await
+}
+
 fn _txm_output(mut output: impl Write, data: &[Value]) -> Result<(), io::Error> {
     let body = data.iter().map(|page| {
         let source = xml_escape(page.get("content").and_then(Value::as_str).unwrap_or_else(|| panic!("Content absent but --txm used (internal error): {page}")));
@@ -127,10 +191,17 @@ struct ListPages<'a> {
     download_content: bool,
     download_html: Option<&'a Path>,
     get_files: bool,
+    files_browser: bool,
+    get_votes: bool,
+    get_styles: bool,
+    download_files: Option<AttachmentDownloader>,
     source_contains_one: bool,
     threads: usize,
     regexes_in_source: Box<[Regex]>,
-    crom: Crom
+    crom: Crom,
+    ajax: WikidotAjax,
+    /// Applied to the webpages of --download-html. The exported data is anonymized by the caller.
+    anonymizer: Option<Anonymizer>,
 }
 
 impl<'a> ListPages<'a> {
@@ -151,46 +222,27 @@ impl<'a> ListPages<'a> {
             })
             .collect();
 
-        let crom_recursive_query_builder = |operation|
-            move |acc: String, tag| {
-                let tag_filter = format!("{{ tags: {{ eq: \"{tag}\" }} }}");
-                if acc.is_empty() {
-                    tag_filter
-                } else {
-                    format!("{{ _{operation}: [{tag_filter}, {acc}] }}")
-                }
-            };
-
-        let filter_and = script_data
-            .all_tags
-            .iter()
-            .fold(String::new(), crom_recursive_query_builder("and"));
-
-        let filter_or = script_data
-            .one_of_tags
-            .iter()
-            .fold(String::new(), crom_recursive_query_builder("or"));
-
-        let filter = match (filter_or.as_str(), filter_and.as_str()) {
-            ("", "") => None,
-            ("", yes) | (yes, "") => Some(yes.to_string()),
-            (or, and) => Some(format!("{{ _and: [ {and}, {or} ] }}")),
-        };
-
         Self {
             verbose: global_data.verbose,
             site: global_data.site.as_ref().unwrap(),
-            filter,
-            author: script_data.author.as_deref(),
+            filter: script_data.selection.crom_filter(),
+            author: script_data.selection.author.as_deref(),
             requested_data: info,
             gather_fragments_sources: script_data.gather_fragments_sources,
             download_content: script_data.content,
             download_html,
             threads: global_data.threads,
             get_files: script_data.files,
+            files_browser: script_data.files_browser,
+            get_votes: script_data.votes,
+            get_styles: script_data.styles,
+            download_files: script_data.download_files.as_ref()
+                .map(|folder| AttachmentDownloader::new(global_data.site.as_ref().unwrap(), Path::new(folder))),
             source_contains_one: script_data.source_contains_one,
             regexes_in_source,
-            crom: Crom::new(global_data.verbose)
+            crom: Crom::new(global_data.verbose),
+            ajax: WikidotAjax::new(global_data.site.as_ref().unwrap(), global_data.verbose),
+            anonymizer: script_data.anonymize.anonymizer(),
         }
     }
 
@@ -199,7 +251,7 @@ impl<'a> ListPages<'a> {
             dbg!(&self);
         }
 
-        let browser_handler = if self.get_files { Some(open_browser(false).await) } else { None };
+        let browser_handler = if self.files_browser { Some(open_browser(false).await) } else { None };
 
         const _LOADING: fn(u64) -> String = |i| (0..i).map(move |n| if n+1 == i {"*"} else {"_"}).collect::<Box<[_]>>().concat();
 
@@ -243,14 +295,33 @@ impl<'a> ListPages<'a> {
             self._gather_fragments_sources(pages.as_mut()).await;
         }
 
+        if self.get_styles {
+            pages.iter_mut()
+                .filter_map(Value::as_object_mut)
+                .for_each(|page| {
+                    let source = page.get("wikidotInfo")
+                        .and_then(|wikidot_info| wikidot_info.get("source"))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    let styles = serde_json::to_value(style_inventory(source)).unwrap();
+                    page.insert("styles".to_string(), styles);
+                });
+        }
+
         if self.download_content || self.get_files || self.download_html.is_some() {
             let htmls = self._download_html(browser_handler.as_ref().map(|(a, _)| a), pages.as_mut()).await;
 
             if let Some(folder) = self.download_html.and_then(|h| h.to_str()) {
-                self._write_htmls(folder, pages.as_ref(), htmls.as_ref()).await;
+                match self.anonymizer.as_ref() {
+                    Some(anonymizer) => {
+                        let htmls = anonymizer.anonymize_htmls(htmls.as_ref(), pages.as_ref());
+                        self._write_htmls(folder, pages.as_ref(), htmls.as_ref()).await;
+                    }
+                    None => self._write_htmls(folder, pages.as_ref(), htmls.as_ref()).await,
+                }
             }
 
-            if self.download_content || self.get_files {
+            if self.download_content || self.files_browser {
                 let parsed_htmls = htmls.iter().map(String::as_str)
                     .map(|s| async {Html::parse_document(s)})
                     .into_future_iter()
@@ -271,24 +342,46 @@ impl<'a> ListPages<'a> {
                         });
                 }
 
-                if self.get_files {
-                    parsed_htmls.iter().map(|html| async {file_list(html)})
-                        .into_future_iter().buffered(self.threads).collect::<Vec<_>>().await
-                        .into_iter()
-                        .zip(pages.iter_mut())
-                        .for_each(|(file_list, page)| {
-                            if let Some(page) = page.as_object_mut() {
-                                page.insert("files".to_string(), serde_json::to_value(file_list).unwrap());
+                if self.files_browser {
+                    let browser = browser_handler.as_ref().map(|(browser, _)| browser).unwrap();
+                    let file_lists = pages.iter().zip(parsed_htmls.iter())
+                        .map(async |(page, html)| match self._has_own_html(page) {
+                            true => file_list(html),
+                            false => {
+                                let url = page.get("url").and_then(Value::as_str).unwrap_or_default();
+                                let html = download_webpage_browser(url, browser).await.unwrap_or_default();
+                                file_list(&Html::parse_document(&html))
                             }
-                        });
+                        })
+                        .into_future_iter().buffered(self.threads).collect::<Vec<_>>().await;
+                    self._insert_files(pages.as_mut(), file_lists).await;
                 }
             }
+
+            if self.get_files && !self.files_browser {
+                let urls_htmls = pages.iter()
+                    .zip(htmls.iter())
+                    .map(|(page, html)| (
+                        page.get("url").and_then(Value::as_str).unwrap_or_default().to_string(),
+                        self._has_own_html(page).then(|| html.clone()),
+                    ))
+                    .collect();
+                let file_lists = attachments::list_pages_files(&self.ajax, urls_htmls, false, self.threads).await
+                    .into_iter()
+                    .map(Option::unwrap_or_default)
+                    .collect();
+                self._insert_files(pages.as_mut(), file_lists).await;
+            }
         }
 
         if let Some(browser_handler) = browser_handler {
             close_browser(browser_handler).await;
         }
 
+        if self.get_votes {
+            self._insert_votes(pages.as_mut()).await;
+        }
+
         let _source_contains = |page: &Value| {
             page.get("wikidotInfo")
                 .and_then(|wikidot_info| wikidot_info.get("source"))
@@ -399,17 +492,11 @@ impl<'a> ListPages<'a> {
     }
 
     async fn _write_htmls(&self, folder: &str, pages: &[Value], htmls: &[String]) {
-        let pages_names = pages.iter()
-            .map(|page|
-                page.get("url")
-                    .and_then(|url| url.as_str())
-                    .and_then(|url| url.split("/").last())
-                    .expect("Malformed url?")
-            ).collect::<Box<[_]>>();
+        let pages_names = pages.iter().map(Self::_page_name).collect::<Box<[_]>>();
         htmls.iter()
-            .zip(pages_names.into_iter())
+            .zip(pages_names)
             .map(async |(html, page_name)|
-                fs::File::open(format!("{folder}/{page_name}.html"))?
+                fs::File::create(format!("{folder}/{page_name}.html"))?
                     .write_all(html.as_bytes())
             )
             .into_future_iter()
@@ -421,6 +508,82 @@ impl<'a> ListPages<'a> {
             .for_each(|e| eprintln!("Could not write file: {:#?}", e));
     }
 
+    /// Downloads the files if requested, then adds the file lists to the pages.
+    async fn _insert_files(&self, pages: &mut [Value], mut file_lists: Vec<Box<[File]>>) {
+        let pages_files = pages.iter()
+            .map(Self::_page_name)
+            .zip(file_lists.iter_mut().map(AsMut::as_mut));
+        attachments::fetch_files_details(&self.ajax, self.site, pages_files, self.threads).await;
+
+        if let Some(downloader) = self.download_files.as_ref() {
+            let entries = file_lists.iter_mut()
+                .zip(pages.iter())
+                .map(|(files, page)| downloader.download_page_files(Self::_page_name(page), files))
+                .into_future_iter()
+                .buffer_unordered(self.threads)
+                .collect::<Vec<_>>()
+                .await;
+            downloader.save_manifest(entries.into_iter().flatten());
+        }
+
+        file_lists.into_iter()
+            .zip(pages.iter_mut())
+            .for_each(|(file_list, page)| {
+                if let Some(page) = page.as_object_mut() {
+                    page.insert("files".to_string(), serde_json::to_value(file_list).unwrap());
+                }
+            });
+    }
+
+    /// Adds the list of votes to the pages, from Crom if it has them, otherwise from Wikidot.
+    async fn _insert_votes(&self, pages: &mut [Value]) {
+        let first_url = pages.first().and_then(|page| page.get("url")).and_then(Value::as_str).unwrap_or_default();
+        let use_crom = match votes::crom_votes(&self.crom, first_url).await {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Votes unavailable from Crom ({e}), asking Wikidot.");
+                false
+            }
+        };
+
+        let page_votes = pages.iter()
+            .map(|page| page.get("url").and_then(Value::as_str).unwrap_or_default())
+            .map(async |url| {
+                println!("Downloading the votes of {url}");
+                if use_crom {
+                    votes::crom_votes(&self.crom, url).await
+                } else {
+                    votes::wikidot_votes(&self.ajax, url).await
+                }.inspect_err(|e| eprintln!("[WARNING] Couldn't get the votes of {url}: {e}")).ok()
+            })
+            .into_future_iter()
+            .buffered(self.threads)
+            .collect::<Vec<_>>()
+            .await;
+
+        page_votes.into_iter()
+            .zip(pages.iter_mut())
+            .for_each(|(votes, page)| {
+                if let Some(page) = page.as_object_mut() {
+                    page.insert("votes".to_string(), serde_json::to_value(votes).unwrap());
+                }
+            });
+    }
+
+    /// Unix name of a page, from its URL.
+    fn _page_name(page: &Value) -> &str {
+        page.get("url")
+            .and_then(|url| url.as_str())
+            .and_then(|url| url.split("/").last())
+            .expect("Malformed url?")
+    }
+
+    /// Whether the downloaded HTML of a page is its own, and not the HTML of its fragments.
+    /// The page ID and the files are only found in the former.
+    fn _has_own_html(&self, page: &Value) -> bool {
+        !self.gather_fragments_sources || Self::_list_children(page).is_empty()
+    }
+
     fn _list_children(page: &Value) -> Box<[&Value]> {
         page.get("wikidotInfo")
             .and_then(|wikidotinfo| wikidotinfo.get("children"))
@@ -0,0 +1,64 @@
+use crate::attachments;
+use crate::common_tools;
+use crate::list_pages::crom::Crom;
+use crate::wikidot_ajax::WikidotAjax;
+use lazy_static::lazy_static;
+use scraper::Selector;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+
+/// Vote data asked to Crom. Not every Crom instance exposes it: Wikidot is asked instead when it fails.
+const CROM_VOTES_DATA: &str = "wikidotInfo { votes { value, user { name } } }";
+
+lazy_static!(
+    static ref VT_SEL_PRINTUSER: Selector = Selector::parse("span.printuser").unwrap();
+    static ref VT_SEL_VALUE: Selector = Selector::parse("span[style^=\"color\"]").unwrap();
+);
+
+#[derive(Serialize)]
+pub struct Vote {
+    voter: String,
+    /// +1 or -1, or the number of stars on wikis rating with stars.
+    vote: i64,
+}
+
+pub async fn crom_votes(crom: &Crom, url: &str) -> Result<Box<[Vote]>, Box<dyn Error>> {
+    let response = crom.try_query(&format!("query {{ page(url: \"{url}\") {{ {CROM_VOTES_DATA} }} }}")).await?;
+    let votes = response.get("data")
+        .and_then(|data| data.get("page"))
+        .and_then(|page| page.get("wikidotInfo"))
+        .and_then(|info| info.get("votes"))
+        .and_then(Value::as_array)
+        .ok_or("no vote data in Crom's response")?;
+    Ok(votes.iter()
+        .map(|vote| Vote {
+            voter: vote.get("user").and_then(|user| user.get("name")).and_then(Value::as_str).unwrap_or_default().to_string(),
+            vote: vote.get("value").and_then(Value::as_i64).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Votes listed by the "who rated this page" module.
+pub async fn wikidot_votes(ajax: &WikidotAjax, url: &str) -> Result<Box<[Vote]>, Box<dyn Error>> {
+    let page_id = common_tools::download_webpage(url).await.as_deref()
+        .and_then(attachments::page_id)
+        .ok_or("no page ID found")?;
+    let html = ajax.module_html("pagerate/WhoRatedPageModule", &[("pageId", page_id.to_string().as_str())]).await?;
+
+    /* Each user is followed by their vote */
+    Ok(html.select(&VT_SEL_PRINTUSER)
+        .zip(html.select(&VT_SEL_VALUE))
+        .map(|(user, value)| {
+            let value = value.text().collect::<String>();
+            Vote {
+                voter: user.text().collect::<String>().trim().to_string(),
+                vote: match value.trim() {
+                    "+" => 1,
+                    "-" => -1,
+                    stars => stars.parse().unwrap_or_default(),
+                },
+            }
+        })
+        .collect())
+}
@@ -1,7 +1,13 @@
 extern crate core;
 
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+mod anonymize;
+#[cfg(feature = "list-pages")]
+mod attachments;
 mod cli;
 mod common_tools;
+#[cfg(feature = "list-files")]
+mod image_hash;
 #[cfg(feature = "forum-dl")]
 mod forum_dl;
 
@@ -11,10 +17,51 @@ mod list_pages;
 #[cfg(feature = "list-files")]
 mod list_files;
 
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "watch"))]
+mod session;
+
+#[cfg(feature = "image-audit")]
+mod image_audit;
+
+#[cfg(feature = "page-history")]
+mod page_history;
+
+#[cfg(feature = "members")]
+mod members;
+
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "backup")]
+mod backup;
+
+#[cfg(feature = "diff")]
+mod diff;
+
+#[cfg(feature = "link-graph")]
+mod link_graph;
+
+#[cfg(feature = "check-links")]
+mod check_links;
+
+#[cfg(feature = "components")]
+mod components;
+
+#[cfg(feature = "styles-summary")]
+mod styles_summary;
+
+#[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "list-files", feature = "watch"))]
+mod wikidot_ajax;
+#[cfg(feature = "list-pages")]
+mod wikidot_source;
+
+#[cfg(feature = "forum-dl")]
 use crate::forum_dl::forum_dl;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
 use cli::Cli;
 use cli::Script;
+#[cfg(feature = "list-files")]
 use crate::list_files::list_files;
 
 #[tokio::main(flavor = "multi_thread")]
@@ -24,6 +71,14 @@ async fn main() {
     if let Some(branch) = args.branch.as_ref() {
         args.site = Some(branch.get_url().to_string());
     }
+    if args.site.is_none() && args.script.needs_site() {
+        Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "this script needs --branch or --site")
+            .exit();
+    }
+
+    #[cfg(any(feature = "list-pages", feature = "forum-dl", feature = "watch"))]
+    session::init(args.login.as_deref(), args.cookies.as_deref()).await;
 
     match args.script {
         #[cfg(feature = "list-pages")]
@@ -32,5 +87,25 @@ async fn main() {
         Script::ForumDl(_) => forum_dl(args).await,
         #[cfg(feature = "list-files")]
         Script::ListFiles(_) => list_files(args).await,
+        #[cfg(feature = "image-audit")]
+        Script::ImageAudit(_) => image_audit::run(args).await,
+        #[cfg(feature = "page-history")]
+        Script::PageHistory(_) => page_history::run(args).await,
+        #[cfg(feature = "members")]
+        Script::Members(_) => members::run(args).await,
+        #[cfg(feature = "watch")]
+        Script::Watch(_) => watch::run(args).await,
+        #[cfg(feature = "backup")]
+        Script::Backup(_) => backup::run(args).await,
+        #[cfg(feature = "diff")]
+        Script::Diff(_) => diff::run(args).await,
+        #[cfg(feature = "link-graph")]
+        Script::LinkGraph(_) => link_graph::run(args).await,
+        #[cfg(feature = "check-links")]
+        Script::CheckLinks(_) => check_links::run(args).await,
+        #[cfg(feature = "components")]
+        Script::Components(_) => components::run(args).await,
+        #[cfg(feature = "styles-summary")]
+        Script::StylesSummary(_) => styles_summary::run(args).await,
     }
 }
@@ -0,0 +1,169 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::FutureIterator;
+use crate::list_pages::Crom;
+use crate::wikidot_ajax::WikidotAjax;
+use chrono::DateTime;
+use clap::Parser;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct MembersParameters {
+    /// Adds statistics from Crom on each member: number of pages attributed to them on the site, total and mean rating.
+    #[arg(long, default_value = "false")]
+    crom_stats: bool,
+}
+
+lazy_static!(
+    static ref MB_SEL_ROW: Selector = Selector::parse("tr").unwrap();
+    static ref MB_SEL_PRINTUSER: Selector = Selector::parse(".printuser").unwrap();
+    static ref MB_SEL_LINK: Selector = Selector::parse("a").unwrap();
+    static ref MB_SEL_ODATE: Selector = Selector::parse(".odate").unwrap();
+    static ref MB_SEL_PAGER_LINK: Selector = Selector::parse(".pager a").unwrap();
+    static ref MB_REGEX_USER_ID: Regex = Regex::new(r"userInfo\((\d+)\)").unwrap();
+);
+
+#[derive(Serialize)]
+struct Member {
+    username: String,
+    user_id: Option<u64>,
+    joined: Option<String>,
+    role: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statistics: Option<Value>,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::Members(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let ajax = WikidotAjax::new(script_data.site.as_ref().unwrap(), script_data.verbose);
+
+    println!("Downloading the members list…");
+    let mut members = _group_members(&ajax, "", script_data.threads).await
+        .expect("Couldn't download the members list");
+
+    /* The main list doesn't show roles: staff are found in the lists of their group */
+    let mut roles = BTreeMap::new();
+    for (group, role) in [("moderators", "moderator"), ("admins", "admin")] {
+        match _group_members(&ajax, group, script_data.threads).await {
+            Ok(staff) => staff.into_iter().for_each(|member| { roles.insert(member.username, role); }),
+            Err(e) => eprintln!("[WARNING] Couldn't download the list of {group}: {e}"),
+        }
+    }
+    members.iter_mut()
+        .for_each(|member| member.role = roles.get(&member.username).copied().unwrap_or("member"));
+
+    if params.crom_stats {
+        let crom = Crom::new(script_data.verbose);
+        let statistics = members.iter()
+            .map(|member| _crom_statistics(&crom, script_data.site.as_deref().unwrap(), &member.username))
+            .into_future_iter()
+            .buffered(script_data.threads)
+            .collect::<Vec<_>>()
+            .await;
+        members.iter_mut()
+            .zip(statistics)
+            .for_each(|(member, statistics)| member.statistics = statistics);
+    }
+
+    println!("{} member(s) found, {} of them staff.", members.len(), roles.len());
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, members.as_ref());
+    println!("Results written in file {}", path);
+}
+
+/// Members of a group ("" for everyone, "moderators" or "admins"), through all the pages of the list.
+async fn _group_members(ajax: &WikidotAjax, group: &str, threads: usize) -> Result<Vec<Member>, Box<dyn Error>> {
+    let first_page = _members_page(ajax, group, 1).await?;
+    let page_count = first_page.select(&MB_SEL_PAGER_LINK)
+        .filter_map(|link| link.text().collect::<String>().trim().parse::<usize>().ok())
+        .max()
+        .unwrap_or(1);
+
+    let other_pages = (2..=page_count)
+        .map(|page_nb| _members_page(ajax, group, page_nb))
+        .into_future_iter()
+        .buffered(threads)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut seen = BTreeSet::new();
+    Ok([first_page].iter().chain(other_pages.iter())
+        .flat_map(_parse_members)
+        .filter(|member| seen.insert(member.username.clone()))
+        .collect())
+}
+
+async fn _members_page(ajax: &WikidotAjax, group: &str, page_nb: usize) -> Result<Html, Box<dyn Error>> {
+    ajax.module_html("membership/MembersListModule", &[("group", group), ("page", page_nb.to_string().as_str())]).await
+}
+
+fn _parse_members(html: &Html) -> Vec<Member> {
+    html.select(&MB_SEL_ROW)
+        .filter_map(|row| {
+            let user = row.select(&MB_SEL_PRINTUSER).next()?;
+            let username = user.select(&MB_SEL_LINK).last()
+                .map(|link| link.text().collect::<String>())
+                .unwrap_or_else(|| user.text().collect())
+                .trim()
+                .to_string();
+            let user_id = user.select(&MB_SEL_LINK)
+                .filter_map(|link| link.attr("onclick"))
+                .find_map(|onclick| MB_REGEX_USER_ID.captures(onclick))
+                .and_then(|captures| captures[1].parse().ok());
+            let joined = row.select(&MB_SEL_ODATE).next()
+                .and_then(|odate| odate.value().classes().find_map(|class| class.strip_prefix("time_")))
+                .and_then(|timestamp| timestamp.parse::<i64>().ok())
+                .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+                .map(|date| date.to_rfc3339());
+            Some(Member { username, user_id, joined, role: "member", statistics: None })
+        })
+        .collect()
+}
+
+/// Attributed pages of a member on this site, with their total and mean rating.
+/// Crom's own user statistics cover all the wikis it knows, so they're computed from the pages instead.
+async fn _crom_statistics(crom: &Crom, site: &str, username: &str) -> Option<Value> {
+    let mut ratings = vec![];
+    let mut after = None;
+    loop {
+        let after_query = after.as_ref().map(|after| format!("after: \"{after}\",")).unwrap_or_default();
+        let query = format!(
+            "query {{ user(name: \"{}\") {{ attributedPages({after_query} filter: {{ url: {{ startsWith: \"{site}\" }} }}) {{ \
+            edges {{ node {{ wikidotInfo {{ rating }} }} }}, pageInfo {{ endCursor, hasNextPage }} }} }} }}",
+            username.replace('\\', "\\\\").replace('"', "\\\""),
+        );
+        let response = crom.try_query(&query).await
+            .inspect_err(|e| eprintln!("[WARNING] Couldn't get the statistics of {username} from Crom: {e}"))
+            .ok()?;
+        let pages = response.pointer("/data/user/attributedPages").filter(|pages| !pages.is_null())?;
+        ratings.extend(pages.get("edges").and_then(Value::as_array).into_iter().flatten()
+            .map(|edge| edge.pointer("/node/wikidotInfo/rating").and_then(Value::as_i64).unwrap_or_default()));
+
+        after = pages.pointer("/pageInfo/hasNextPage").and_then(Value::as_bool).unwrap_or(false)
+            .then(|| pages.pointer("/pageInfo/endCursor").and_then(Value::as_str).map(String::from))
+            .flatten();
+        if after.is_none() {
+            break;
+        }
+    }
+
+    let total = ratings.iter().sum::<i64>();
+    Some(serde_json::json!({
+        "pageCount": ratings.len(),
+        "totalRating": total,
+        "meanRating": if ratings.is_empty() { 0.0 } else { total as f64 / ratings.len() as f64 },
+    }))
+}
@@ -0,0 +1,190 @@
+use crate::attachments;
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::common_tools::FutureIterator;
+use crate::list_pages::{select_pages, PageSelection};
+use crate::wikidot_ajax::{source_text, WikidotAjax};
+use chrono::DateTime;
+use clap::Parser;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// Revisions asked per request. Wikidot accepts large pages, which saves requests on long histories.
+const REVISIONS_PER_REQUEST: usize = 1000;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct PageHistoryParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Also downloads the source of every revision.
+    #[arg(long, default_value = "false")]
+    sources: bool,
+}
+
+lazy_static!(
+    static ref PH_SEL_ROW: Selector = Selector::parse("tr[id^=\"revision-row-\"]").unwrap();
+    static ref PH_SEL_TD: Selector = Selector::parse("td").unwrap();
+    static ref PH_SEL_FLAG: Selector = Selector::parse("span").unwrap();
+    static ref PH_SEL_PRINTUSER: Selector = Selector::parse(".printuser").unwrap();
+    static ref PH_SEL_ODATE: Selector = Selector::parse(".odate").unwrap();
+);
+
+#[derive(Serialize)]
+struct PageHistory {
+    url: String,
+    title: String,
+    revisions: Box<[Revision]>,
+}
+
+#[derive(Serialize)]
+pub struct Revision {
+    number: u32,
+    id: u64,
+    editor: String,
+    date: Option<String>,
+    timestamp: Option<i64>,
+    flags: Box<[String]>,
+    comment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::PageHistory(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let ajax = WikidotAjax::new(script_data.site.as_ref().unwrap(), script_data.verbose);
+
+    let pages = select_pages(&script_data, &params.selection, &["url", "wikidotInfo.title"]).await;
+
+    let histories = pages.iter()
+        .map(|page| _page_history(&ajax, page, params.sources))
+        .into_future_iter()
+        .buffered(script_data.threads)
+        .filter_map(async |history| history)
+        .collect::<Vec<_>>()
+        .await;
+
+    println!(
+        "{} revision(s) of {} page(s) downloaded.",
+        histories.iter().map(|history| history.revisions.len()).sum::<usize>(),
+        histories.len(),
+    );
+
+    let path = script_data.output.path().clone();
+    common_tools::write_out(script_data, histories.as_ref());
+    println!("Results written in file {}", path);
+}
+
+async fn _page_history(ajax: &WikidotAjax, page: &Value, sources: bool) -> Option<PageHistory> {
+    let url = page.get("url").and_then(Value::as_str).unwrap_or_default();
+    let title = page.get("wikidotInfo")
+        .and_then(|info| info.get("title"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    println!("Downloading the history of {url}");
+
+    let Some(page_id) = common_tools::download_webpage(url).await.as_deref().and_then(attachments::page_id) else {
+        eprintln!("[WARNING] No page ID found for {url}, skipping it.");
+        return None;
+    };
+
+    let mut revisions = revisions(ajax, page_id).await
+        .inspect_err(|e| eprintln!("[WARNING] Couldn't get the history of {url}: {e}"))
+        .ok()?;
+
+    if sources {
+        for revision in revisions.iter_mut() {
+            revision.source = _revision_source(ajax, revision.id).await
+                .inspect_err(|e| eprintln!("[WARNING] Couldn't get the source of revision {} of {url}: {e}", revision.number))
+                .ok();
+        }
+    }
+
+    Some(PageHistory {
+        url: url.to_string(),
+        title: title.to_string(),
+        revisions: revisions.into_boxed_slice(),
+    })
+}
+
+/// Every revision of a page, oldest first.
+pub async fn revisions(ajax: &WikidotAjax, page_id: u64) -> Result<Vec<Revision>, Box<dyn Error>> {
+    let mut revisions = Vec::new();
+    let mut seen = BTreeSet::new();
+    for page_nb in 1.. {
+        let html = ajax.module_html("history/PageRevisionListModule", &[
+            ("page_id", page_id.to_string().as_str()),
+            ("options", "{\"all\":true}"),
+            ("page", page_nb.to_string().as_str()),
+            ("perpage", REVISIONS_PER_REQUEST.to_string().as_str()),
+        ]).await?;
+        /* Rows that can't be parsed would make a full page look short: only a page without anything new ends the list.
+        Past the last page, Wikidot may answer with the last one again. */
+        let page_revisions = _parse_revisions(&html).into_iter()
+            .filter(|revision| seen.insert(revision.id))
+            .collect::<Vec<_>>();
+        if page_revisions.is_empty() {
+            break;
+        }
+        revisions.extend(page_revisions);
+    }
+    revisions.sort_by_key(|revision| revision.number);
+    Ok(revisions)
+}
+
+/// Rows of the history table: number, compare buttons, flags, actions, editor, date, comment.
+fn _parse_revisions(html: &Html) -> Vec<Revision> {
+    html.select(&PH_SEL_ROW)
+        .filter_map(|row| {
+            let id = row.value().id()?.strip_prefix("revision-row-")?.parse().ok()?;
+            let cells = row.select(&PH_SEL_TD).collect::<Box<[_]>>();
+            let text = |cell: Option<&ElementRef>| cell.map(|cell| cell.text().collect::<String>().trim().to_string()).unwrap_or_default();
+
+            let timestamp = row.select(&PH_SEL_ODATE).next()
+                .and_then(|odate| odate.value().classes().find_map(|class| class.strip_prefix("time_")))
+                .and_then(|timestamp| timestamp.parse::<i64>().ok());
+            Some(Revision {
+                number: text(cells.first()).trim_end_matches('.').parse().ok()?,
+                id,
+                editor: row.select(&PH_SEL_PRINTUSER).next()
+                    .map(|user| user.text().collect::<String>().trim().to_string())
+                    .unwrap_or_default(),
+                date: timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp, 0)).map(|date| date.to_rfc3339()),
+                timestamp,
+                flags: cells.get(2)
+                    .map(|cell| cell.select(&PH_SEL_FLAG)
+                        .map(|flag| _flag_name(flag.text().collect::<String>().trim()))
+                        .filter(|flag| !flag.is_empty())
+                        .collect())
+                    .unwrap_or_default(),
+                comment: text(cells.last()),
+                source: None,
+            })
+        })
+        .collect()
+}
+
+fn _flag_name(flag: &str) -> String {
+    match flag {
+        "N" => "new page",
+        "S" => "source",
+        "T" => "title",
+        "R" => "rename",
+        "A" => "tags",
+        "M" => "metadata",
+        "F" => "files",
+        other => other,
+    }.to_string()
+}
+
+async fn _revision_source(ajax: &WikidotAjax, revision_id: u64) -> Result<String, Box<dyn Error>> {
+    let html = ajax.module_html("history/PageSourceModule", &[("revision_id", revision_id.to_string().as_str())]).await?;
+    Ok(source_text(&html))
+}
@@ -0,0 +1,172 @@
+#[cfg(feature = "list-pages")]
+use chromiumoxide::Browser;
+#[cfg(feature = "list-pages")]
+use chromiumoxide::cdp::browser_protocol::network::CookieParam;
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+use reqwest::cookie::Jar;
+use reqwest::header::{SET_COOKIE, USER_AGENT};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use std::fs;
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+const LOGIN_URL: &str = "https://www.wikidot.com/default--flow/login__LoginPopupScreen";
+/// Domain of the cookies given without one, and of the session cookie Wikidot sets at login.
+const WIKIDOT_DOMAIN: &str = ".wikidot.com";
+/// The only cookie needed to be logged in. Other cookies of a browser export are left out.
+const SESSION_COOKIE: &str = "WIKIDOT_SESSION_ID";
+
+/// Cookies of the Wikidot session, shared by the HTTP clients and the browsers of the whole run.
+static SESSION: OnceLock<Box<[SessionCookie]>> = OnceLock::new();
+/// The same cookies, only sent to Wikidot over https.
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+static JAR: OnceLock<Arc<Jar>> = OnceLock::new();
+
+#[derive(Debug)]
+struct SessionCookie {
+    name: String,
+    value: String,
+    domain: String,
+}
+
+/// Establishes the session: logs in with the username (the password is read from WIKIDOT_PASSWORD or asked),
+/// or imports the cookies of a file. Without either, the scripts browse Wikidot anonymously.
+pub async fn init(login: Option<&str>, cookies_file: Option<&str>) {
+    let cookies = match (login, cookies_file) {
+        (Some(username), _) => _login(username).await,
+        (None, Some(path)) => _read_cookies(path),
+        (None, None) => return,
+    };
+    if !cookies.iter().any(|cookie| cookie.name == SESSION_COOKIE) {
+        eprintln!("[WARNING] No {SESSION_COOKIE} cookie for wikidot.com: Wikidot will probably treat you as logged out.");
+    }
+    SESSION.set(cookies).expect("Session initialized twice");
+}
+
+/// The session's cookies as a `Cookie` header value, for requests to an https URL of Wikidot.
+pub fn cookie_header(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok().filter(|url| url.scheme() == "https")?;
+    if !url.host_str().is_some_and(_is_wikidot_host) {
+        return None;
+    }
+    SESSION.get()
+        .filter(|cookies| !cookies.is_empty())
+        .map(|cookies| cookies.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect::<Box<[_]>>().join("; "))
+}
+
+/// HTTP client sending the session's cookies to Wikidot, only over https. Other websites never get them.
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+pub fn http_client() -> reqwest::Client {
+    let jar = JAR.get_or_init(|| {
+        let jar = Jar::default();
+        for cookie in SESSION.get().into_iter().flatten() {
+            let domain = cookie.domain.trim_start_matches('.');
+            let url = Url::parse(&format!("https://{domain}/")).expect("Cookie domain isn't a host name");
+            jar.add_cookie_str(&format!("{}={}; Domain={domain}; Path=/; Secure", cookie.name, cookie.value), &url);
+        }
+        Arc::new(jar)
+    });
+    reqwest::Client::builder()
+        .cookie_provider(jar.clone())
+        .build()
+        .expect("Failed to build the HTTP client")
+}
+
+/// Wikidot redirects http to https: asking for https directly keeps the session cookie off plain http.
+#[cfg(any(feature = "list-pages", feature = "forum-dl"))]
+pub fn https(url: &str) -> String {
+    match url.strip_prefix("http://") {
+        Some(rest) if Url::parse(url).ok().and_then(|url| url.host_str().map(_is_wikidot_host)).unwrap_or(false) => format!("https://{rest}"),
+        _ => url.to_string(),
+    }
+}
+
+fn _is_wikidot_host(host: &str) -> bool {
+    let host = host.to_lowercase();
+    host == "wikidot.com" || host.ends_with(".wikidot.com")
+}
+
+#[cfg(feature = "list-pages")]
+/// Gives the session's cookies to a browser.
+pub async fn apply_to_browser(browser: &Browser) {
+    let Some(cookies) = SESSION.get() else {
+        return;
+    };
+    let params = cookies.iter()
+        .map(|cookie| CookieParam::builder()
+            .name(cookie.name.as_str())
+            .value(cookie.value.as_str())
+            .domain(cookie.domain.as_str())
+            .path("/")
+            .secure(true)
+            .build()
+            .expect("Cookie without name or value"))
+        .collect();
+    if let Err(e) = browser.set_cookies(params).await {
+        eprintln!("[WARNING] Could not give the session cookies to the browser: {e}");
+    }
+}
+
+async fn _login(username: &str) -> Box<[SessionCookie]> {
+    let password = std::env::var("WIKIDOT_PASSWORD").unwrap_or_else(|_| {
+        rpassword::prompt_password(format!("Wikidot password for {username}: "))
+            .expect("Could not read the password")
+    });
+
+    /* The session cookie is set by the login response itself, not by the page it redirects to */
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to build the HTTP client");
+    let response = client.post(LOGIN_URL)
+        .header(USER_AGENT, "ScpScriptAnthology/1.0")
+        .form(&[("login", username), ("password", password.as_str()), ("action", "Login2Action"), ("event", "login")])
+        .send().await
+        .expect("Could not reach Wikidot's login form");
+
+    let cookies = response.headers().get_all(SET_COOKIE).iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .filter_map(|cookie| cookie.split(';').next()?.split_once('='))
+        .filter(|(name, value)| name.trim() == SESSION_COOKIE && !value.is_empty() && *value != "deleted")
+        .map(|(name, value)| SessionCookie {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+            domain: WIKIDOT_DOMAIN.to_string(),
+        })
+        .collect::<Box<[_]>>();
+
+    let body = response.text().await.unwrap_or_default();
+    if body.contains("The login and password do not match") || cookies.is_empty() {
+        panic!("Login failed: check the username and the password.");
+    }
+    println!("Logged in as {username}.");
+    cookies
+}
+
+/// Reads the session cookie of Wikidot from a Netscape cookies.txt file (as exported by browsers), or `name=value` lines.
+/// Cookies of other websites, and other cookies of Wikidot, are ignored.
+fn _read_cookies(path: &str) -> Box<[SessionCookie]> {
+    let content = fs::read_to_string(path).unwrap_or_else(|e| panic!("Could not read cookies file {path}: {e}"));
+    content.lines()
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line).trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(|line| match line.split('\t').collect::<Box<[_]>>().as_ref() {
+            [domain, _, _, _, _, name, value] => vec![SessionCookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: domain.to_string(),
+            }],
+            _ => line.trim_start_matches("Cookie:").split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| SessionCookie {
+                    name: name.trim().to_string(),
+                    value: value.trim().to_string(),
+                    domain: WIKIDOT_DOMAIN.to_string(),
+                })
+                .collect(),
+        })
+        .filter(|cookie| cookie.name == SESSION_COOKIE && _is_wikidot_host(cookie.domain.trim_start_matches('.')))
+        .collect()
+}
@@ -0,0 +1,183 @@
+use crate::cli::{Cli, Script};
+use crate::common_tools;
+use crate::list_pages::{select_pages, PageSelection};
+use crate::wikidot_source::{style_inventory, StyleInventory};
+use clap::Parser;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct StylesSummaryParameters {
+    #[command(flatten)]
+    selection: PageSelection,
+    /// Reads the pages from a list-pages output (JSON or YAML) made with --styles or with the sources,
+    /// instead of asking Crom. --branch and --site aren't needed then.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all_tags", "one_of_tags", "author"])]
+    from: Option<String>,
+}
+
+impl StylesSummaryParameters {
+    pub fn needs_site(&self) -> bool {
+        self.from.is_none()
+    }
+}
+
+lazy_static!(
+    static ref SS_REGEX_CSS_RULE: Regex = Regex::new(r"([^{}]+)\{").unwrap();
+    static ref SS_REGEX_CSS_COMMENT: Regex = Regex::new(r"(?s)/\*.*?\*/").unwrap();
+);
+
+#[derive(Serialize)]
+struct StylesSummary {
+    pages: usize,
+    /// Theme includes, with the pages using them.
+    themes: Vec<Usage>,
+    /// Pages with `[[module CSS]]` blocks.
+    pages_with_css: Vec<String>,
+    /// Selectors of the `[[module CSS]]` blocks, by number of pages using them.
+    css_selectors: Vec<Count>,
+    modules: Vec<Count>,
+    div_classes: Vec<Count>,
+}
+
+#[derive(Serialize)]
+struct Usage {
+    name: String,
+    pages: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Count {
+    name: String,
+    pages: usize,
+}
+
+pub async fn run(script_data: Cli) {
+    let Script::StylesSummary(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+
+    let pages = match params.from.as_ref() {
+        Some(path) => {
+            let content = fs::read_to_string(path).unwrap_or_else(|e| panic!("--from: could not read {path}: {e}"));
+            serde_yaml::from_str::<Vec<Value>>(&content)
+                .unwrap_or_else(|e| panic!("--from: {path} isn't a list-pages output: {e}"))
+                .into_boxed_slice()
+        }
+        None => select_pages(&script_data, &params.selection, &["url", "wikidotInfo.source"]).await,
+    };
+
+    let inventories = pages.iter()
+        .map(|page| {
+            let url = page.get("url").and_then(Value::as_str).unwrap_or_default().to_string();
+            let inventory = match page.get("styles") {
+                Some(styles) => _read_inventory(styles),
+                None => style_inventory(page.pointer("/wikidotInfo/source").and_then(Value::as_str).unwrap_or_default()),
+            };
+            (url, inventory)
+        })
+        .collect::<Vec<_>>();
+
+    let mut themes = BTreeMap::<&str, Vec<String>>::new();
+    let mut selectors = BTreeMap::<String, usize>::new();
+    let mut modules = BTreeMap::<&str, usize>::new();
+    let mut div_classes = BTreeMap::<&str, usize>::new();
+    for (url, inventory) in &inventories {
+        inventory.themes.iter().for_each(|theme| themes.entry(theme).or_default().push(url.clone()));
+        inventory.modules.iter().for_each(|module| *modules.entry(module).or_default() += 1);
+        inventory.div_classes.iter().for_each(|class| *div_classes.entry(class).or_default() += 1);
+
+        let mut page_selectors = inventory.css_blocks.iter()
+            .flat_map(|css| _css_selectors(css))
+            .collect::<Vec<_>>();
+        page_selectors.sort();
+        page_selectors.dedup();
+        page_selectors.into_iter().for_each(|selector| *selectors.entry(selector).or_default() += 1);
+    }
+
+    let summary = StylesSummary {
+        pages: inventories.len(),
+        themes: _sorted(themes.into_iter().map(|(name, pages)| Usage { name: name.to_string(), pages }), |usage| usage.pages.len()),
+        pages_with_css: inventories.iter()
+            .filter(|(_, inventory)| !inventory.css_blocks.is_empty())
+            .map(|(url, _)| url.clone())
+            .collect(),
+        css_selectors: _counts(selectors),
+        modules: _counts(modules),
+        div_classes: _counts(div_classes),
+    };
+    println!(
+        "{} page(s): {} theme(s), {} page(s) with custom CSS, {} module(s), {} div class(es).",
+        summary.pages,
+        summary.themes.len(),
+        summary.pages_with_css.len(),
+        summary.modules.len(),
+        summary.div_classes.len(),
+    );
+
+    let path = script_data.output.path().clone();
+    common_tools::write_serialized(script_data.output, &script_data.output_format, &summary);
+    println!("Results written in file {}", path);
+}
+
+/// The "styles" field written by list-pages --styles.
+fn _read_inventory(styles: &Value) -> StyleInventory {
+    let strings = |field: &str| styles.get(field).and_then(Value::as_array).into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(String::from);
+    StyleInventory {
+        css_blocks: strings("css_blocks").collect(),
+        themes: strings("themes").collect(),
+        modules: strings("modules").collect(),
+        div_classes: strings("div_classes").collect(),
+    }
+}
+
+/// Selectors of the rules of a stylesheet, at-rules excluded.
+fn _css_selectors(css: &str) -> Vec<String> {
+    SS_REGEX_CSS_RULE.captures_iter(&SS_REGEX_CSS_COMMENT.replace_all(css, ""))
+        .filter(|captures| !captures[1].trim_start().starts_with('@'))
+        .flat_map(|captures| captures[1].split(',').map(|selector| selector.split_whitespace().collect::<Vec<_>>().join(" ")).collect::<Vec<_>>())
+        .filter(|selector| !selector.is_empty())
+        .collect()
+}
+
+fn _counts<K: ToString>(counts: BTreeMap<K, usize>) -> Vec<Count> {
+    _sorted(counts.into_iter().map(|(name, pages)| Count { name: name.to_string(), pages }), |count| count.pages)
+}
+
+/// Most used first.
+fn _sorted<T>(items: impl Iterator<Item = T>, key: impl Fn(&T) -> usize) -> Vec<T> {
+    let mut items = items.collect::<Vec<_>>();
+    items.sort_by_key(|item| std::cmp::Reverse(key(item)));
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_selectors_of_rules() {
+        assert_eq!(_css_selectors("#page-title { display: none; }\n.a,\n .b  .c{color:red}"), ["#page-title", ".a", ".b .c"]);
+    }
+
+    #[test]
+    fn skips_at_rules_but_not_their_rules() {
+        let css = "@media screen and (max-width: 600px), print {\n  .sidebar { display: none }\n  @supports (display: grid) { .grid > div { gap: 1em } }\n}\n\
+            @font-face { font-family: X; src: url(x.woff) }\n.after { margin: 0 }";
+        assert_eq!(_css_selectors(css), [".sidebar", ".grid > div", ".after"]);
+    }
+
+    #[test]
+    fn ignores_comments() {
+        let css = "/* .commented { color: red } */ .kept /* inline, comment */ { color: blue }\n/*\n.multiline {}\n*/";
+        assert_eq!(_css_selectors(css), [".kept"]);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::cli::{Cli, Script};
+use crate::wikidot_ajax::WikidotAjax;
+use chrono::DateTime;
+use clap::Parser;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const CHANGES_PER_PAGE: usize = 100;
+/// Pages of changes read at most per poll, when many changes happened since the previous one.
+const MAX_CHANGES_PAGES: usize = 10;
+/// Pages of recent forum posts read at most per poll.
+const MAX_FORUM_PAGES: usize = 10;
+
+#[derive(Parser)]
+#[command(version = "0.1.0")]
+pub struct WatchParameters {
+    /// File keeping what was already seen between polls and between runs. Created on the first run,
+    /// which only records the current state without emitting events.
+    #[arg(long, value_name = "FILE", default_value = "watch-state.json")]
+    state: String,
+    /// Seconds between two polls.
+    #[arg(long, default_value = "300")]
+    interval: u64,
+    /// Polls once and exits, e.g. to run the script from a scheduler.
+    #[arg(long, default_value = "false")]
+    once: bool,
+    /// Command run for every event, with the event as JSON on its standard input.
+    /// Unix only: the command is run by `sh -c`.
+    #[arg(long, value_name = "COMMAND")]
+    hook: Option<String>,
+    /// Doesn't watch the forum.
+    #[arg(long, default_value = "false")]
+    no_forum: bool,
+}
+
+lazy_static!(
+    static ref WA_SEL_CHANGE: Selector = Selector::parse("div.changes-list-item").unwrap();
+    static ref WA_SEL_CHANGE_TITLE: Selector = Selector::parse("td.title a").unwrap();
+    static ref WA_SEL_CHANGE_FLAG: Selector = Selector::parse("td.flags span").unwrap();
+    static ref WA_SEL_CHANGE_REVISION: Selector = Selector::parse("td.revision-no").unwrap();
+    static ref WA_SEL_CHANGE_COMMENT: Selector = Selector::parse("div.comments").unwrap();
+    static ref WA_SEL_POST: Selector = Selector::parse("div.post").unwrap();
+    static ref WA_SEL_POST_TITLE: Selector = Selector::parse(".title").unwrap();
+    static ref WA_SEL_LINK: Selector = Selector::parse("a[href]").unwrap();
+    static ref WA_SEL_PRINTUSER: Selector = Selector::parse(".printuser").unwrap();
+    static ref WA_SEL_ODATE: Selector = Selector::parse(".odate").unwrap();
+    static ref WA_REGEX_POST_ID: Regex = Regex::new(r"post-(\d+)").unwrap();
+    static ref WA_REGEX_NUMBER: Regex = Regex::new(r"\d+").unwrap();
+);
+
+/// What was already seen, saved between runs.
+#[derive(Serialize, Deserialize, Default)]
+struct WatchState {
+    /// Time of the most recent change seen.
+    last_change: i64,
+    /// Changes seen at that time ("page#revision"), since several can share a second.
+    last_change_keys: BTreeSet<String>,
+    last_post_id: u64,
+}
+
+struct PageChange {
+    key: String,
+    timestamp: i64,
+    event: serde_json::Value,
+}
+
+pub async fn run(mut script_data: Cli) {
+    let Script::Watch(params) = &script_data.script else {
+        panic!("Unreachable code")
+    };
+    let site = script_data.site.clone().unwrap();
+    let ajax = WikidotAjax::new(site.as_str(), script_data.verbose);
+
+    let mut state = fs::read_to_string(&params.state).ok()
+        .map(|state| serde_json::from_str::<WatchState>(&state)
+            .unwrap_or_else(|e| panic!("State file {} is corrupted: {e}", params.state)));
+    let mut initializing = state.is_none();
+
+    loop {
+        let mut current = state.take().unwrap_or_default();
+        let mut events = _poll_changes(&ajax, &mut current).await
+            .inspect_err(|e| eprintln!("[WARNING] Couldn't get the recent changes: {e}"))
+            .unwrap_or_default();
+        if !params.no_forum {
+            events.extend(_poll_forum(&ajax, site.as_str(), &mut current).await
+                .inspect_err(|e| eprintln!("[WARNING] Couldn't get the recent forum posts: {e}"))
+                .unwrap_or_default());
+        }
+
+        if initializing {
+            eprintln!("State initialized, events will be emitted from the next poll.");
+            initializing = false;
+        } else {
+            for event in &events {
+                _emit(&mut script_data.output, params.hook.as_deref(), event);
+            }
+        }
+
+        fs::write(&params.state, serde_json::to_string_pretty(&current).unwrap())
+            .unwrap_or_else(|e| eprintln!("Could not write the state file {}: {e}", params.state));
+        state = Some(current);
+
+        if params.once {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(params.interval)).await;
+    }
+}
+
+fn _emit(output: &mut impl Write, hook: Option<&str>, event: &serde_json::Value) {
+    writeln!(output, "{event}")
+        .and_then(|_| output.flush())
+        .unwrap_or_else(|e| eprintln!("Could not write event: {e}"));
+
+    if let Some(hook) = hook {
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .stdin(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(event.to_string().as_bytes())?;
+                child.wait()
+            });
+        match result {
+            Ok(status) if !status.success() => eprintln!("[WARNING] Hook exited with {status}."),
+            Err(e) => eprintln!("[WARNING] Could not run the hook: {e}"),
+            _ => {}
+        }
+    }
+}
+
+/// New page creations, edits and tag changes, oldest first.
+async fn _poll_changes(ajax: &WikidotAjax, state: &mut WatchState) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    let mut changes = Vec::new();
+    for page_nb in 1..=MAX_CHANGES_PAGES {
+        let html = ajax.module_html("changes/SiteChangesListModule", &[
+            ("options", "{\"all\":true}"),
+            ("page", page_nb.to_string().as_str()),
+            ("perpage", CHANGES_PER_PAGE.to_string().as_str()),
+        ]).await?;
+        let page_changes = _parse_changes(&html);
+        let done = page_changes.len() < CHANGES_PER_PAGE
+            || state.last_change == 0
+            || page_changes.iter().any(|change| change.timestamp < state.last_change);
+        changes.extend(page_changes);
+        if done {
+            break;
+        }
+        if page_nb == MAX_CHANGES_PAGES {
+            eprintln!("[WARNING] More than {} changes since the previous poll, the older ones are missed.", MAX_CHANGES_PAGES * CHANGES_PER_PAGE);
+        }
+    }
+
+    let new_changes = changes.into_iter()
+        .filter(|change| change.timestamp > state.last_change
+            || (change.timestamp == state.last_change && !state.last_change_keys.contains(&change.key)))
+        .rev()
+        .collect::<Vec<_>>();
+
+    if let Some(last) = new_changes.iter().map(|change| change.timestamp).max() {
+        if last > state.last_change {
+            state.last_change = last;
+            state.last_change_keys.clear();
+        }
+        state.last_change_keys.extend(new_changes.iter()
+            .filter(|change| change.timestamp == last)
+            .map(|change| change.key.clone()));
+    }
+    Ok(new_changes.into_iter().map(|change| change.event).collect())
+}
+
+fn _parse_changes(html: &Html) -> Vec<PageChange> {
+    html.select(&WA_SEL_CHANGE)
+        .filter_map(|item| {
+            let link = item.select(&WA_SEL_CHANGE_TITLE).next()?;
+            let url = link.attr("href")?.to_string();
+            let timestamp = _odate_timestamp(item)?;
+            let revision = item.select(&WA_SEL_CHANGE_REVISION).next()
+                .and_then(|revision| WA_REGEX_NUMBER.find(&revision.text().collect::<String>()).and_then(|number| number.as_str().parse::<u32>().ok()));
+            let flags = item.select(&WA_SEL_CHANGE_FLAG)
+                .map(|flag| flag.text().collect::<String>().trim().to_string())
+                .collect::<Box<[_]>>();
+            let event_type = if flags.iter().any(|flag| flag == "N") {
+                "page_created"
+            } else if flags.iter().any(|flag| flag == "A") {
+                "tags_changed"
+            } else {
+                "page_edited"
+            };
+
+            Some(PageChange {
+                key: format!("{url}#{}", revision.unwrap_or_default()),
+                timestamp,
+                event: json!({
+                    "type": event_type,
+                    "url": url,
+                    "title": link.text().collect::<String>().trim(),
+                    "revision": revision,
+                    "flags": flags,
+                    "user": _text(item.select(&WA_SEL_PRINTUSER).next()),
+                    "date": _rfc3339(timestamp),
+                    "comment": _text(item.select(&WA_SEL_CHANGE_COMMENT).next()),
+                }),
+            })
+        })
+        .collect()
+}
+
+/// New forum posts, oldest first. Post IDs only grow, so the last one seen is enough.
+/// Pages of recent posts are read until one has a post already seen (only the first on the first run).
+async fn _poll_forum(ajax: &WikidotAjax, site: &str, state: &mut WatchState) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    let mut posts = Vec::new();
+    for page_nb in 1..=MAX_FORUM_PAGES {
+        let html = ajax.module_html("forum/ForumRecentPostsModule", &[("page", page_nb.to_string().as_str()), ("categoryId", "")]).await?;
+        let page_posts = _parse_posts(&html, site);
+        let done = page_posts.is_empty()
+            || state.last_post_id == 0
+            || page_posts.iter().any(|(id, _)| *id <= state.last_post_id);
+        posts.extend(page_posts);
+        if done {
+            break;
+        }
+        if page_nb == MAX_FORUM_PAGES {
+            eprintln!("[WARNING] More than {MAX_FORUM_PAGES} pages of forum posts since the previous poll, the older ones are missed.");
+        }
+    }
+
+    let mut posts = posts.into_iter()
+        .filter(|(id, _)| *id > state.last_post_id)
+        .collect::<Vec<_>>();
+    posts.sort_by_key(|(id, _)| *id);
+    posts.dedup_by_key(|(id, _)| *id);
+
+    if let Some((last, _)) = posts.last() {
+        state.last_post_id = *last;
+    }
+    Ok(posts.into_iter().map(|(_, event)| event).collect())
+}
+
+fn _parse_posts(html: &Html, site: &str) -> Vec<(u64, serde_json::Value)> {
+    html.select(&WA_SEL_POST)
+        .filter_map(|post| {
+            let id = post.value().id()
+                .and_then(|id| WA_REGEX_POST_ID.captures(id))
+                .or_else(|| post.select(&WA_SEL_LINK).filter_map(|link| link.attr("href")).find_map(|href| WA_REGEX_POST_ID.captures(href)))
+                .and_then(|captures| captures[1].parse::<u64>().ok())?;
+            let thread = post.select(&WA_SEL_LINK)
+                .filter_map(|link| link.attr("href"))
+                .find(|href| href.contains("/forum/t-"))
+                .map(|href| format!("{}/{}", site.trim_end_matches('/'), href.trim_start_matches('/').split('#').next().unwrap_or_default()));
+            Some((id, json!({
+                "type": "forum_post",
+                "post_id": id,
+                "thread": thread,
+                "title": _text(post.select(&WA_SEL_POST_TITLE).next()),
+                "user": _text(post.select(&WA_SEL_PRINTUSER).next()),
+                "date": _odate_timestamp(post).map(_rfc3339),
+            })))
+        })
+        .collect()
+}
+
+fn _text(element: Option<ElementRef>) -> String {
+    element.map(|element| element.text().collect::<String>().trim().to_string()).unwrap_or_default()
+}
+
+fn _odate_timestamp(element: ElementRef) -> Option<i64> {
+    element.select(&WA_SEL_ODATE).next()
+        .and_then(|odate| odate.value().classes().find_map(|class| class.strip_prefix("time_")))
+        .and_then(|timestamp| timestamp.parse().ok())
+}
+
+fn _rfc3339(timestamp: i64) -> Option<String> {
+    DateTime::from_timestamp(timestamp, 0).map(|date| date.to_rfc3339())
+}
@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use reqwest::header::{COOKIE, USER_AGENT};
+use lazy_static::lazy_static;
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+/// Any value works, as long as the cookie and the form field are the same.
+const WIKIDOT_TOKEN: &str = "ssa0000000";
+
+/// Client for `ajax-module-connector.php`, the endpoint Wikidot's own front-end uses to load modules.
+#[derive(Debug)]
+pub struct WikidotAjax {
+    client: reqwest::Client,
+    connector_url: String,
+    /// The token cookie, followed by the session's cookies if any.
+    cookies: String,
+    verbose: bool,
+}
+
+impl WikidotAjax {
+    pub fn new(site: &str, verbose: bool) -> Self {
+        /* Wikidot redirects http to https, and a redirected POST becomes a GET. */
+        let site = site.replacen("http://", "https://", 1);
+        let connector_url = format!("{}/ajax-module-connector.php", site.trim_end_matches('/'));
+        Self {
+            client: reqwest::Client::new(),
+            cookies: [Some(format!("wikidot_token7={WIKIDOT_TOKEN}")), crate::session::cookie_header(&connector_url)]
+                .into_iter().flatten().collect::<Box<[_]>>().join("; "),
+            connector_url,
+            verbose,
+        }
+    }
+
+    /// Calls a module and returns the whole JSON response. Fails if Wikidot doesn't answer with an "ok" status.
+    pub async fn query(&self, module_name: &str, params: &[(&str, &str)]) -> Result<Value, Box<dyn Error>> {
+        let form = params.iter()
+            .copied()
+            .chain([("moduleName", module_name), ("wikidot_token7", WIKIDOT_TOKEN)])
+            .collect::<Box<[_]>>();
+
+        if self.verbose {
+            println!("Wikidot module query: {module_name} {params:?}");
+        }
+
+        /* Only network errors and "try_again" are retried, other statuses won't change by retrying. */
+        let response = crate::common_tools::retry_async(5, Some(Duration::from_secs(5)), async || {
+            let response: Value = self.client
+                .post(self.connector_url.as_str())
+                .header(USER_AGENT, "ScpScriptAnthology/1.0")
+                .header(COOKIE, self.cookies.as_str())
+                .form(&form)
+                .send().await
+                .inspect_err(|e| eprintln!("Request error: {e}. Retrying in 5 seconds."))?
+                .json().await
+                .inspect_err(|e| eprintln!("Recieved data is not in JSON? {e} Retrying in 5 seconds."))?;
+
+            if self.verbose {
+                println!("Response: {response}");
+            }
+
+            if response.get("status").and_then(Value::as_str) == Some("try_again") {
+                eprintln!("Wikidot asked to try again later. Retrying in 5 seconds.");
+                return Err(Box::<dyn Error>::from(WikidotError::from_response(&response)));
+            }
+            Ok(response)
+        }).await?;
+
+        match response.get("status").and_then(Value::as_str) {
+            Some("ok") => Ok(response),
+            _ => Err(WikidotError::from_response(&response).into()),
+        }
+    }
+
+    /// Calls a module and parses the HTML it renders.
+    pub async fn module_html(&self, module_name: &str, params: &[(&str, &str)]) -> Result<Html, Box<dyn Error>> {
+        let response = self.query(module_name, params).await?;
+        let body = response.get("body")
+            .and_then(Value::as_str)
+            .ok_or_else(|| WikidotError::from_response(&response))?;
+        Ok(Html::parse_fragment(body))
+    }
+
+    #[cfg(feature = "image-audit")]
+    /// Current Wikidot source of a page, for the pages Crom doesn't know.
+    pub async fn page_source(&self, page_id: u64) -> Result<String, Box<dyn Error>> {
+        let html = self.module_html("viewsource/ViewSourceModule", &[("page_id", page_id.to_string().as_str())]).await?;
+        Ok(source_text(&html))
+    }
+}
+
+lazy_static!(
+    static ref WA_SEL_SOURCE: Selector = Selector::parse(".page-source").unwrap();
+);
+
+#[cfg(any(feature = "image-audit", feature = "page-history"))]
+/// Text of the `.page-source` block rendered by Wikidot's source modules.
+pub fn source_text(html: &Html) -> String {
+    html.select(&WA_SEL_SOURCE).next()
+        .map(|source| source.text().collect::<String>())
+        .unwrap_or_default()
+        .replace('\u{a0}', " ")
+        .trim()
+        .to_string()
+}
+
+#[derive(Debug)]
+pub struct WikidotError {
+    status: String,
+    message: String,
+}
+
+impl WikidotError {
+    fn from_response(response: &Value) -> Self {
+        let field = |name| response.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+        Self {
+            status: field("status"),
+            message: field("message"),
+        }
+    }
+}
+
+impl Display for WikidotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wikidot returned status \"{}\": {}", self.status, self.message)
+    }
+}
+
+impl Error for WikidotError {}
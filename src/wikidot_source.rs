@@ -0,0 +1,317 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+#[cfg(any(feature = "list-files", feature = "image-audit", feature = "link-graph", feature = "check-links"))]
+use reqwest::Url;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+const IMAGE_EXTENSIONS: [&str; 10] = ["jpg", "jpeg", "png", "gif", "webp", "svg", "bmp", "tif", "tiff", "avif"];
+
+lazy_static!(
+    static ref WS_REGEX_MODULE: Regex = Regex::new(r"(?i)\[\[\s*[=<>f]*\s*(image|file)\s+([^\s\]|]+)").unwrap();
+    static ref WS_REGEX_GALLERY: Regex = Regex::new(r"(?is)\[\[gallery[^\]]*\]\](.*?)\[\[/gallery\]\]").unwrap();
+    static ref WS_REGEX_GALLERY_ITEM: Regex = Regex::new(r"(?m)^\s*:\s*(\S+)").unwrap();
+    static ref WS_REGEX_IMG: Regex = Regex::new(r#"(?i)<img[^>]+src\s*=\s*["']([^"']+)"#).unwrap();
+    static ref WS_REGEX_TRIPLE_LINK: Regex = Regex::new(r"\[\[\[\s*\*?([^\]|#]*?)\s*(?:#[^\]|]*)?(?:\|[^\]]*)?\]\]\]").unwrap();
+    static ref WS_REGEX_RELATIVE_LINK: Regex = Regex::new(r#"(?:(?:^|[^\[])\[\s*\*?(/[^\s\]]*)\s|href\s*=\s*["'](/[^"'\s]*))"#).unwrap();
+    static ref WS_REGEX_URL: Regex = Regex::new(r#"(?i)https?://[^\s\[\]|"'<>]+"#).unwrap();
+    static ref WS_REGEX_INCLUDE: Regex = Regex::new(r"(?is)\[\[\s*include\s+([^\s\]|]+)(.*?)\]\]").unwrap();
+    static ref WS_REGEX_CSS_MODULE: Regex = Regex::new(r"(?is)\[\[\s*module\s+css\b[^\]]*\]\](.*?)\[\[/module\]\]").unwrap();
+    static ref WS_REGEX_MODULE_NAME: Regex = Regex::new(r"(?i)\[\[\s*module\s+([\w/]+)").unwrap();
+    static ref WS_REGEX_DIV_CLASS: Regex = Regex::new(r#"(?i)\[\[\s*div_?\s[^\]]*?\bclass\s*=\s*"([^"]*)""#).unwrap();
+    static ref WS_REGEX_LOCAL_FILES: Regex = Regex::new(r#"(?i)(?:(?:https?:)?//([\w.\-]+))?/local--files/([^/\s"'<>\]\)|]+)/([^\s"'<>\]\)|?#]+)"#).unwrap();
+);
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+/// A file used in a page source.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileReference {
+    /// File attached to a page of the site: the page of the source when `page` is `None`.
+    Attached { page: Option<String>, name: String },
+    /// File hosted outside of the site.
+    External(String),
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+impl FileReference {
+    /// Name of the file if it is attached to the given page.
+    pub fn attached_to(&self, page_name: &str) -> Option<&str> {
+        match self {
+            Self::Attached { page: None, name } => Some(name),
+            Self::Attached { page: Some(page), name } if page == page_name => Some(name),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "link-graph")]
+/// How a page refers to another page of the site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Link,
+    Include,
+}
+
+#[cfg(feature = "link-graph")]
+/// A page of the site referred to by a source, by its unix name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageLink {
+    pub target: String,
+    pub kind: LinkKind,
+}
+
+/// An `[[include]]` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Include {
+    /// Wiki of the included page, for `[[include :wiki:page]]`.
+    pub site: Option<String>,
+    /// Unix name of the included page.
+    pub page: String,
+    pub arguments: Vec<(String, String)>,
+}
+
+impl Include {
+    /// `page`, or `:wiki:page` for a page of another wiki, as written in sources.
+    pub fn name(&self) -> String {
+        match &self.site {
+            Some(site) => format!(":{site}:{}", self.page),
+            None => self.page.clone(),
+        }
+    }
+}
+
+/// What a source does with the look of its page.
+#[derive(Debug, Default, Serialize)]
+pub struct StyleInventory {
+    /// Contents of the `[[module CSS]]` blocks.
+    pub css_blocks: Vec<String>,
+    /// Included pages with "theme" in their name.
+    pub themes: BTreeSet<String>,
+    /// Modules used, in lowercase (listpages, rate, comments…).
+    pub modules: BTreeSet<String>,
+    /// Classes given to `[[div]]` blocks.
+    pub div_classes: BTreeSet<String>,
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+pub fn is_image(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+#[cfg(feature = "list-files")]
+/// Files used by a source: images, `[[file]]` links and any `local--files` or `*.wdfiles.com` URL.
+pub fn file_references(source: &str, site: &str) -> BTreeSet<FileReference> {
+    _references(source, site, false)
+}
+
+#[cfg(feature = "image-audit")]
+/// Images displayed by a source: `[[image]]` modules, galleries, `<img>` tags and image files linked with `local--files`.
+pub fn image_references(source: &str, site: &str) -> BTreeSet<FileReference> {
+    _references(source, site, true)
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+fn _references(source: &str, site: &str, images_only: bool) -> BTreeSet<FileReference> {
+    let site_host = Url::parse(site).ok().and_then(|url| url.host_str().map(String::from));
+    let site_host = site_host.as_deref();
+
+    let modules = WS_REGEX_MODULE.captures_iter(source)
+        .filter(|captures| !images_only || captures[1].eq_ignore_ascii_case("image"))
+        .map(|captures| captures.get(2).unwrap().as_str());
+    let gallery_items = WS_REGEX_GALLERY.captures_iter(source)
+        .flat_map(|gallery| WS_REGEX_GALLERY_ITEM.captures_iter(gallery.get(1).unwrap().as_str())
+            .map(|item| item.get(1).unwrap().as_str())
+            .collect::<Vec<_>>());
+    let imgs = WS_REGEX_IMG.captures_iter(source).map(|captures| captures.get(1).unwrap().as_str());
+
+    let mut references = modules.chain(gallery_items).chain(imgs)
+        .filter_map(|target| _classify(target, site_host))
+        .collect::<BTreeSet<_>>();
+
+    references.extend(WS_REGEX_LOCAL_FILES.captures_iter(source)
+        .filter(|captures| !images_only || is_image(&captures[3]))
+        .map(|captures| _local_file(captures.get(1).map(|host| host.as_str()), &captures[2], &captures[3], captures[0].to_string(), site_host)));
+
+    references
+}
+
+#[cfg(feature = "link-graph")]
+/// Pages of the site linked by a source: `[[[page]]]` links, `[/page text]` and `href="/page"` links,
+/// URLs of the site, and `[[include page]]` (includes from other wikis are left out).
+pub fn page_links(source: &str, site: &str) -> BTreeSet<PageLink> {
+    let site_host = Url::parse(site).ok().and_then(|url| url.host_str().map(String::from));
+    let link = |target| PageLink { target, kind: LinkKind::Link };
+
+    let triple_links = WS_REGEX_TRIPLE_LINK.captures_iter(source)
+        .filter_map(|captures| {
+            let target = captures.get(1).unwrap().as_str();
+            if target.contains("//") {
+                page_name_from_url(target, site_host.as_deref())
+            } else {
+                Some(unix_name(target.trim_start_matches('/'))).filter(|name| !name.is_empty())
+            }
+        })
+        .map(link);
+    let relative_links = WS_REGEX_RELATIVE_LINK.captures_iter(source)
+        .filter_map(|captures| page_name_from_url(captures.get(1).or(captures.get(2)).unwrap().as_str(), site_host.as_deref()))
+        .map(link);
+    let urls = WS_REGEX_URL.find_iter(source)
+        .filter_map(|url| page_name_from_url(url.as_str(), site_host.as_deref()))
+        .map(link);
+    let includes = includes(source).into_iter()
+        .filter(|include| include.site.is_none())
+        .map(|include| PageLink { target: include.page, kind: LinkKind::Include });
+
+    triple_links.chain(relative_links).chain(urls).chain(includes).collect()
+}
+
+/// `[[include page arg1=value | arg2=value]]` directives of a source, in order.
+pub fn includes(source: &str) -> Vec<Include> {
+    WS_REGEX_INCLUDE.captures_iter(source)
+        .map(|captures| {
+            let target = captures.get(1).unwrap().as_str();
+            let (site, page) = match target.strip_prefix(':').and_then(|target| target.split_once(':')) {
+                Some((site, page)) => (Some(site.to_lowercase()), page),
+                None => (None, target),
+            };
+            Include {
+                site,
+                page: unix_name(page),
+                arguments: captures[2].split('|')
+                    .filter_map(|argument| argument.split_once('='))
+                    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                    .filter(|(name, _)| !name.is_empty())
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+pub fn style_inventory(source: &str) -> StyleInventory {
+    StyleInventory {
+        css_blocks: WS_REGEX_CSS_MODULE.captures_iter(source)
+            .map(|captures| captures[1].trim().to_string())
+            .collect(),
+        themes: includes(source).iter()
+            .map(Include::name)
+            .filter(|name| name.contains("theme"))
+            .collect(),
+        modules: WS_REGEX_MODULE_NAME.captures_iter(source)
+            .map(|captures| captures[1].to_lowercase())
+            .collect(),
+        div_classes: WS_REGEX_DIV_CLASS.captures_iter(source)
+            .flat_map(|captures| captures[1].split_whitespace().map(String::from).collect::<Vec<_>>())
+            .collect(),
+    }
+}
+
+#[cfg(feature = "check-links")]
+/// URLs of other websites in a source, wherever they appear: links, images, modules or HTML.
+/// Files of the site (on its `wdfiles.com` host) aren't outbound.
+pub fn external_links(source: &str, site: &str) -> BTreeSet<String> {
+    let site_host = Url::parse(site).ok().and_then(|url| url.host_str().map(str::to_lowercase));
+    let files_host = site_host.as_deref()
+        .and_then(|host| host.strip_suffix(".wikidot.com"))
+        .map(|wiki| format!("{wiki}.wdfiles.com"));
+
+    WS_REGEX_URL.find_iter(source)
+        .map(|url| url.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '*']))
+        .filter(|url| Url::parse(url).ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .is_some_and(|host| Some(&host) != site_host.as_ref() && Some(&host) != files_host.as_ref()))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(feature = "link-graph")]
+/// Unix name of the page of the site an URL (absolute or starting with "/") leads to.
+/// Files, forum threads and other special URLs lead to no page.
+pub fn page_name_from_url(url: &str, site_host: Option<&str>) -> Option<String> {
+    let path = match url.strip_prefix('/').filter(|path| !path.starts_with('/')) {
+        Some(path) => path,
+        None => {
+            let url = Url::parse(url).ok()?;
+            if url.host_str()?.to_lowercase() != site_host?.to_lowercase() {
+                return None;
+            }
+            return page_name_from_url(url.path(), site_host);
+        }
+    };
+    let page = path.split(['/', '#', '?']).next().unwrap_or_default();
+    if page.is_empty() || page.contains("--") || page == "forum" {
+        return None;
+    }
+    Some(unix_name(&_percent_decode(page))).filter(|name| !name.is_empty())
+}
+
+/// Page name as Wikidot normalizes it: "Some Page" becomes "some-page".
+pub fn unix_name(name: &str) -> String {
+    let name = name.trim().to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == ':' || c == '_' { c } else { '-' })
+        .collect::<String>();
+    name.split(':')
+        .map(|part| part.split('-').filter(|word| !word.is_empty()).collect::<Box<[_]>>().join("-"))
+        .collect::<Box<[_]>>()
+        .join(":")
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+/// Classifies the target of a module or a tag.
+fn _classify(target: &str, site_host: Option<&str>) -> Option<FileReference> {
+    if target.starts_with(':') || target.is_empty() {
+        return None;
+    }
+    if let Some(captures) = WS_REGEX_LOCAL_FILES.captures(target) {
+        return Some(_local_file(captures.get(1).map(|host| host.as_str()), &captures[2], &captures[3], target.to_string(), site_host));
+    }
+    if target.contains("//") {
+        return Some(FileReference::External(target.to_string()));
+    }
+    Some(match target.trim_start_matches('/').split_once('/') {
+        Some((page, name)) if target.starts_with('/') => FileReference::Attached { page: Some(page.to_lowercase()), name: _percent_decode(name) },
+        _ => FileReference::Attached { page: None, name: _percent_decode(target) },
+    })
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit"))]
+/// `local--files` URLs of another wiki are external files.
+/// Files of `<wiki>.wikidot.com` are served from `<wiki>.wdfiles.com`, any wdfiles.com host is accepted for custom domains.
+fn _local_file(host: Option<&str>, page: &str, name: &str, url: String, site_host: Option<&str>) -> FileReference {
+    let on_site = host.map(str::to_lowercase).is_none_or(|host| match (site_host, host.strip_suffix(".wdfiles.com")) {
+        (Some(site_host), Some(wiki)) => site_host.strip_suffix(".wikidot.com").is_none_or(|site_wiki| site_wiki == wiki),
+        (Some(site_host), None) => host == site_host,
+        (None, wdfiles) => wdfiles.is_some(),
+    });
+    if on_site {
+        FileReference::Attached { page: Some(page.to_lowercase()), name: _percent_decode(name) }
+    } else {
+        FileReference::External(url)
+    }
+}
+
+#[cfg(any(feature = "list-files", feature = "image-audit", feature = "link-graph"))]
+/// File names are often percent-encoded in URLs ("my%20image.png").
+fn _percent_decode(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = name.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| name.to_string())
+}